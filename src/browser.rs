@@ -0,0 +1,212 @@
+//! Browser profiles: opening URLs in a profile or container of a web browser.
+//!
+//! Each browser family selects profiles in its own way; [`Browser`](struct.Browser.html)
+//! produces the matching argument vector for [`sh::dispatch`](../sh/fn.dispatch.html).
+
+use std::slice;
+
+use url::form_urlencoded;
+use url::Url;
+
+/// Browser family, determining the command line arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    /// Firefox and its derivatives; profiles are selected by name (`-P name`).
+    Firefox,
+    /// Chromium, Google Chrome and other Chromium-based browsers;
+    /// profiles are selected by directory (`--profile-directory=dir`).
+    Chromium,
+}
+
+impl Family {
+    /// Name of the browser executable, if not specified.
+    pub fn executable(self) -> &'static str {
+        match self {
+            Family::Firefox => "firefox",
+            Family::Chromium => "chromium",
+        }
+    }
+}
+
+/// Browser opening URLs in a specific profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Browser {
+    family: Family,
+    executable: String,
+    profile: Option<String>,
+    container: Option<String>,
+    new_instance: bool,
+}
+
+impl Browser {
+    /// Open URLs with the default executable and profile of the browser family.
+    pub fn new(family: Family) -> Browser {
+        Browser {
+            family,
+            executable: family.executable().to_owned(),
+            profile: None,
+            container: None,
+            new_instance: false,
+        }
+    }
+
+    /// Run a different executable of the family, such as `firefox-esr` or `google-chrome`.
+    pub fn with_executable<S: Into<String>>(mut self, executable: S) -> Browser {
+        self.executable = executable.into();
+        self
+    }
+
+    /// Open URLs in the profile: the profile name for Firefox,
+    /// the profile directory (such as `Profile 1`) for Chromium.
+    pub fn with_profile<S: Into<String>>(mut self, profile: S) -> Browser {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// Open URLs in the Firefox Multi-Account Container, by its name.
+    ///
+    /// The URL is passed wrapped in an `ext+container:` URL, handled by the extension.
+    /// Chromium has no containers; the container is ignored for it.
+    pub fn with_container<S: Into<String>>(mut self, container: S) -> Browser {
+        self.container = Some(container.into());
+        self
+    }
+
+    /// Start a separate Firefox instance instead of using an already running one,
+    /// which would otherwise open the URL in its own profile.
+    ///
+    /// Chromium always opens the URL in the requested profile; the flag is ignored for it.
+    pub fn with_new_instance(mut self, new_instance: bool) -> Browser {
+        self.new_instance = new_instance;
+        self
+    }
+
+    /// Family of the browser.
+    pub fn family(&self) -> Family {
+        self.family
+    }
+
+    /// Browser executable.
+    pub fn executable(&self) -> &str {
+        &self.executable
+    }
+
+    /// Selected profile, if any.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Selected container, if any.
+    pub fn container(&self) -> Option<&str> {
+        self.container.as_deref()
+    }
+
+    /// Whether a separate instance is started.
+    pub fn new_instance(&self) -> bool {
+        self.new_instance
+    }
+
+    /// Produce an argument vector opening the URL.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate urldispatch;
+    /// use urldispatch::browser::{Browser, Family};
+    /// let url = url::Url::parse("https://intranet.corp.example.com/").unwrap();
+    ///
+    /// let chromium = Browser::new(Family::Chromium).with_profile("Profile 1");
+    /// assert_eq!(
+    ///     chromium.argv(&url),
+    ///     ["chromium", "--profile-directory=Profile 1", "https://intranet.corp.example.com/"]
+    /// );
+    ///
+    /// let firefox = Browser::new(Family::Firefox).with_profile("work").with_container("Corp");
+    /// assert_eq!(
+    ///     firefox.argv(&url),
+    ///     ["firefox", "-P", "work", "ext+container:name=Corp&url=https%3A%2F%2Fintranet.corp.example.com%2F"]
+    /// );
+    /// ```
+    pub fn argv(&self, url: &Url) -> Vec<String> {
+        self.argv_all(slice::from_ref(url))
+    }
+
+    /// Produce an argument vector opening all the URLs at once.
+    pub fn argv_all(&self, urls: &[Url]) -> Vec<String> {
+        let mut argv = vec![self.executable.clone()];
+        match self.family {
+            Family::Firefox => {
+                if let Some(ref profile) = self.profile {
+                    argv.push("-P".to_owned());
+                    argv.push(profile.clone());
+                }
+                if self.new_instance {
+                    argv.push("--new-instance".to_owned());
+                }
+                argv.extend(urls.iter().map(|url| match self.container {
+                    Some(ref container) => container_url(container, url),
+                    None => url.as_str().to_owned(),
+                }));
+            }
+            Family::Chromium => {
+                if let Some(ref profile) = self.profile {
+                    argv.push(format!("--profile-directory={}", profile));
+                }
+                argv.extend(urls.iter().map(Url::to_string));
+            }
+        }
+        argv
+    }
+}
+
+/// URL opening the URL in the Multi-Account Container.
+fn container_url(container: &str, url: &Url) -> String {
+    let query = form_urlencoded::Serializer::new(String::new())
+        .append_pair("name", container)
+        .append_pair("url", url.as_str())
+        .finish();
+    format!("ext+container:{}", query)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builds_browser_argv() {
+        let url = Url::parse("https://example.com/?q=a b").unwrap();
+        assert_eq!(Browser::new(Family::Firefox).argv(&url), ["firefox", "https://example.com/?q=a%20b"]);
+
+        let firefox = Browser::new(Family::Firefox)
+            .with_executable("firefox-esr")
+            .with_profile("personal")
+            .with_new_instance(true);
+        assert_eq!(
+            firefox.argv(&url),
+            ["firefox-esr", "-P", "personal", "--new-instance", "https://example.com/?q=a%20b"]
+        );
+        assert_eq!(
+            Browser::new(Family::Firefox).with_container("Home & Garden").argv(&url),
+            ["firefox", "ext+container:name=Home+%26+Garden&url=https%3A%2F%2Fexample.com%2F%3Fq%3Da%2520b"]
+        );
+
+        let chromium = Browser::new(Family::Chromium)
+            .with_executable("google-chrome")
+            .with_container("ignored")
+            .with_new_instance(true);
+        assert_eq!(chromium.argv(&url), ["google-chrome", "https://example.com/?q=a%20b"]);
+
+        let other = Url::parse("https://example.org/").unwrap();
+        assert_eq!(
+            chromium.argv_all(&[url.clone(), other.clone()]),
+            ["google-chrome", "https://example.com/?q=a%20b", "https://example.org/"]
+        );
+        assert_eq!(
+            Browser::new(Family::Firefox).with_container("Work").argv_all(&[other]),
+            ["firefox", "ext+container:name=Work&url=https%3A%2F%2Fexample.org%2F"]
+        );
+    }
+}
@@ -0,0 +1,1105 @@
+//! Configuration file loading and validation.
+//!
+//! The configuration is a TOML file with launcher definitions and rules:
+//!
+//! ```toml
+//! # Optional; open URLs not matched by any rule with the default application
+//! # from mimeapps.list, as xdg-open does
+//! mimeapps = true
+//!
+//! # Optional; menu for rules that let the user choose the launcher
+//! [chooser]
+//! command = "dmenu -p 'Open %u with'"
+//!
+//! # Optional; command printing the clipboard contents, for --clipboard
+//! [clipboard]
+//! command = "xsel --output --clipboard"
+//!
+//! [launchers.firefox]
+//! command = "firefox --new-window %u"
+//!
+//! [launchers.mpv]
+//! command = "mpv -- %u"
+//! # Optional; all fields default to false/inherit
+//! detach = { session = true, chdir = true, output = "null", close-fds = true }
+//! # Optional; "fork" (default) or "spawn"
+//! backend = "spawn"
+//!
+//! [launchers.firefox-wayland]
+//! command = "firefox %u"
+//! # Optional; environment changes, applied in the order clear, unset, set
+//! clear-env = false
+//! unset-env = ["LD_PRELOAD"]
+//! env = { MOZ_ENABLE_WAYLAND = "1" }
+//! # Optional; working directory (absolute) and file mode creation mask (octal)
+//! cwd = "/tmp"
+//! umask = "077"
+//!
+//! [launchers.emacs]
+//! command = "emacs %f"
+//! # Optional; used instead of command while the process is running
+//! remote = { process = "emacs", command = "emacsclient -n %f" }
+//!
+//! # Instead of command: "firefox" or "chromium", optionally with
+//! # executable, profile (Chromium profile directory) and, for Firefox,
+//! # Multi-Account Container and whether to start a separate instance
+//! [launchers.work]
+//! browser = "firefox"
+//! profile = "work"
+//! container = "Corp"
+//! new-instance = true
+//!
+//! [[rules]]
+//! name = "corp"
+//! host = ".corp.example.com"
+//! launcher = "work"
+//!
+//! [[rules]]
+//! name = "videos"
+//! host = ".youtube.com"
+//! launcher = "mpv"
+//!
+//! [[rules]]
+//! name = "web"
+//! scheme = "https"
+//! launcher = "firefox"
+//! # Optional; tried in order if the launcher program is not found
+//! fallback = ["mpv"]
+//!
+//! [[rules]]
+//! name = "images"
+//! # Type of a local file, from shared-mime-info
+//! mime = "image/*"
+//! launcher = "firefox"
+//!
+//! # Optional; applied before the rules, repeatedly until the URL settles
+//! [[rewrites]]
+//! name = "tracking"
+//! strip-params = ["utm_*", "fbclid"]
+//!
+//! [[rewrites]]
+//! name = "google"
+//! host = "www.google.com"
+//! path = "/url"
+//! unwrap = "q"
+//!
+//! [[rewrites]]
+//! name = "nitter"
+//! host = ".twitter.com"
+//! set-host = "nitter.net"
+//!
+//! [[rules]]
+//! name = "ask"
+//! # Instead of launcher and fallback: choose from these with the menu
+//! choose = ["firefox", "mpv"]
+//! ```
+//!
+//! Rules are tried in order of appearance; see [`rules::Rule`](../rules/struct.Rule.html)
+//! for the meaning of the conditions. Rewrites take the same conditions;
+//! see [`rewrite::Action`](../rewrite/enum.Action.html) for the transformations.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{error, fmt, fs, io};
+
+use regex::Regex;
+use toml::{self, Spanned};
+
+use browser::{Browser, Family};
+use instance::Remote;
+use launcher::{Environment, Launcher};
+use mime::MimePattern;
+use mimeapps::MimeApps;
+use rewrite::{Action, Rewrite, Rewriter};
+use rules::{Conditions, Dispatcher, HostPattern, Rule};
+use sh::{self, Backend, Detach, Output};
+use template::Template;
+use xdg;
+
+/// Location of the configuration file, relative to the XDG configuration directories.
+pub const CONFIG_FILE: &str = "urldispatch/config.toml";
+
+/// Position in the configuration file; both line and column are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    /// Find position of a byte offset in the source.
+    fn at(source: &str, offset: usize) -> Position {
+        let before = &source[..offset.min(source.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+        Position {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+/// Description of a problem in the configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub path: Option<PathBuf>,
+    pub position: Option<Position>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref path) = self.path {
+            write!(f, "{}:", path.display())?;
+        }
+        if let Some(Position { line, column }) = self.position {
+            write!(f, "{}:{}:", line, column)?;
+        }
+        if self.path.is_some() || self.position.is_some() {
+            f.write_str(" ")?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+/// Reasons for failed configuration loading.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// No configuration file exists in any of the listed locations.
+    NotFound(Vec<PathBuf>),
+    /// The configuration file could not be read.
+    Io(PathBuf, io::Error),
+    /// The configuration file is not valid.
+    Invalid(Diagnostic),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::NotFound(ref searched) => {
+                write!(f, "no configuration file found; searched:")?;
+                for path in searched {
+                    write!(f, " {}", path.display())?;
+                }
+                Ok(())
+            }
+            ConfigError::Io(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+            ConfigError::Invalid(ref diagnostic) => diagnostic.fmt(f),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+/// Detach options, as written in the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct DetachEntry {
+    #[serde(default)]
+    session: bool,
+    #[serde(default)]
+    chdir: bool,
+    output: Option<Spanned<String>>,
+    #[serde(default)]
+    close_fds: bool,
+}
+
+/// Running instance handling, as written in the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RemoteEntry {
+    process: Spanned<String>,
+    command: Spanned<String>,
+}
+
+/// Launcher definition, as written in the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct LauncherEntry {
+    command: Option<Spanned<String>>,
+    desktop: Option<Spanned<String>>,
+    browser: Option<Spanned<String>>,
+    executable: Option<Spanned<String>>,
+    profile: Option<Spanned<String>>,
+    container: Option<Spanned<String>>,
+    new_instance: Option<Spanned<bool>>,
+    detach: Option<DetachEntry>,
+    backend: Option<Spanned<String>>,
+    #[serde(default)]
+    env: BTreeMap<String, Spanned<String>>,
+    #[serde(default)]
+    unset_env: Vec<Spanned<String>>,
+    #[serde(default)]
+    clear_env: bool,
+    cwd: Option<Spanned<String>>,
+    umask: Option<Spanned<String>>,
+    remote: Option<RemoteEntry>,
+}
+
+/// Menu definition, as written in the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChooserEntry {
+    command: Spanned<String>,
+}
+
+/// Clipboard access, as written in the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClipboardEntry {
+    command: Spanned<String>,
+}
+
+/// Rule definition, as written in the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+    name: Spanned<String>,
+    scheme: Option<String>,
+    host: Option<Spanned<String>>,
+    path: Option<String>,
+    regex: Option<Spanned<String>>,
+    mime: Option<Spanned<String>>,
+    launcher: Option<Spanned<String>>,
+    #[serde(default)]
+    fallback: Vec<Spanned<String>>,
+    #[serde(default)]
+    choose: Vec<Spanned<String>>,
+}
+
+/// Rewrite definition, as written in the file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RewriteEntry {
+    name: Spanned<String>,
+    scheme: Option<String>,
+    host: Option<Spanned<String>>,
+    path: Option<String>,
+    regex: Option<Spanned<String>>,
+    mime: Option<Spanned<String>>,
+    strip_params: Option<Vec<String>>,
+    unwrap: Option<String>,
+    set_host: Option<Spanned<String>>,
+}
+
+/// Configuration file, as written.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    chooser: Option<ChooserEntry>,
+    clipboard: Option<ClipboardEntry>,
+    #[serde(default)]
+    mimeapps: bool,
+    #[serde(default)]
+    launchers: BTreeMap<String, LauncherEntry>,
+    #[serde(default)]
+    rewrites: Vec<RewriteEntry>,
+    #[serde(default)]
+    rules: Vec<RuleEntry>,
+}
+
+/// Validated configuration.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    dispatcher: Dispatcher,
+    clipboard: Option<Vec<String>>,
+}
+
+/// Validation of the file contents, with access to the source for error reporting.
+struct Validator<'s> {
+    source: &'s str,
+}
+
+impl<'s> Validator<'s> {
+    /// Report a problem with a spanned value.
+    fn error<T, M: fmt::Display>(&self, value: &Spanned<T>, message: M) -> Diagnostic {
+        Diagnostic {
+            path: None,
+            position: Some(Position::at(self.source, value.start())),
+            message: message.to_string(),
+        }
+    }
+
+    fn detach(&self, launcher: &str, entry: DetachEntry) -> Result<Detach, Diagnostic> {
+        let output = match entry.output {
+            None => Output::Inherit,
+            Some(ref output) => match output.get_ref().as_str() {
+                "inherit" => Output::Inherit,
+                "null" => Output::Null,
+                path if path.starts_with('/') => Output::Log(path.into()),
+                _ => {
+                    return Err(self.error(
+                        output,
+                        format_args!("launcher {}: output must be inherit, null or absolute path", launcher),
+                    ))
+                }
+            },
+        };
+
+        Ok(Detach {
+            new_session: entry.session,
+            chdir_root: entry.chdir,
+            output,
+            close_fds: entry.close_fds,
+        })
+    }
+
+    fn environment(
+        &self,
+        launcher: &str,
+        clear: bool,
+        unset: Vec<Spanned<String>>,
+        set: BTreeMap<String, Spanned<String>>,
+    ) -> Result<Environment, Diagnostic> {
+        let valid = |name: &str| !name.is_empty() && !name.contains(['=', '\0']);
+        let mut environment = Environment {
+            clear,
+            ..Environment::default()
+        };
+        for name in unset {
+            if !valid(name.get_ref()) {
+                let message = format_args!("launcher {}: invalid variable name {:?}", launcher, name.get_ref());
+                return Err(self.error(&name, message));
+            }
+            environment.unset.push(name.into_inner());
+        }
+        for (name, value) in set {
+            if !valid(&name) || value.get_ref().contains('\0') {
+                let message = format_args!("launcher {}: invalid variable {:?}", launcher, name);
+                return Err(self.error(&value, message));
+            }
+            environment.set.insert(name, value.into_inner());
+        }
+        Ok(environment)
+    }
+
+    fn browser(
+        &self,
+        launcher: &str,
+        family: &Spanned<String>,
+        entry: &LauncherEntry,
+    ) -> Result<Browser, Diagnostic> {
+        let family = match family.get_ref().as_str() {
+            "firefox" => Family::Firefox,
+            "chromium" => Family::Chromium,
+            _ => {
+                return Err(self.error(
+                    family,
+                    format_args!("launcher {}: browser must be firefox or chromium", launcher),
+                ))
+            }
+        };
+
+        let mut browser = Browser::new(family);
+        if let Some(ref executable) = entry.executable {
+            browser = browser.with_executable(executable.get_ref().as_str());
+        }
+        if let Some(ref profile) = entry.profile {
+            browser = browser.with_profile(profile.get_ref().as_str());
+        }
+        if let Some(ref container) = entry.container {
+            if family != Family::Firefox {
+                let message = format_args!("launcher {}: container requires firefox", launcher);
+                return Err(self.error(container, message));
+            }
+            browser = browser.with_container(container.get_ref().as_str());
+        }
+        if let Some(ref new_instance) = entry.new_instance {
+            if family != Family::Firefox {
+                let message = format_args!("launcher {}: new-instance requires firefox", launcher);
+                return Err(self.error(new_instance, message));
+            }
+            browser = browser.with_new_instance(*new_instance.get_ref());
+        }
+        Ok(browser)
+    }
+
+    fn remote(&self, launcher: &str, entry: &RemoteEntry) -> Result<Remote, Diagnostic> {
+        let process = entry.process.get_ref();
+        if process.is_empty() || (process.contains('/') && !process.starts_with('/')) {
+            let message = format_args!("launcher {}: remote process must be name or absolute path", launcher);
+            return Err(self.error(&entry.process, message));
+        }
+        let template = entry
+            .command
+            .get_ref()
+            .parse()
+            .map_err(|err| self.error(&entry.command, format_args!("launcher {}: remote: {}", launcher, err)))?;
+        Ok(Remote::new(process.as_str(), template))
+    }
+
+    /// Position of the launcher's table: its header or, if it is written inline,
+    /// the first of its values.
+    fn launcher_table(&self, name: &str, entry: &LauncherEntry) -> Option<Position> {
+        let headers = [
+            format!("[launchers.{}]", name),
+            format!("[launchers.\"{}\"]", name),
+            format!("[launchers.'{}']", name),
+        ];
+        let mut offset = 0;
+        for line in self.source.split_inclusive('\n') {
+            let compact: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+            if headers.iter().any(|header| compact.starts_with(header.as_str())) {
+                let indent = line.len() - line.trim_start().len();
+                return Some(Position::at(self.source, offset + indent));
+            }
+            offset += line.len();
+        }
+
+        let values = [&entry.backend, &entry.cwd, &entry.umask, &entry.executable, &entry.profile, &entry.container];
+        let options = values.iter().filter_map(|value| value.as_ref());
+        let options = options.chain(entry.env.values()).chain(&entry.unset_env);
+        options.map(Spanned::start).min().map(|start| Position::at(self.source, start))
+    }
+
+    fn launchers(
+        &self,
+        entries: BTreeMap<String, LauncherEntry>,
+    ) -> Result<BTreeMap<String, Launcher>, Diagnostic> {
+        let mut launchers = BTreeMap::new();
+        for (name, entry) in entries {
+            let programs = [&entry.command, &entry.desktop, &entry.browser];
+            if let Some(extra) = programs.iter().filter_map(|program| program.as_ref()).nth(1) {
+                return Err(self.error(
+                    extra,
+                    format_args!("launcher {}: only one of command, desktop or browser can be set", name),
+                ));
+            }
+            let mut launcher = if let Some(ref command) = entry.command {
+                let template = command
+                    .get_ref()
+                    .parse()
+                    .map_err(|err| self.error(command, format_args!("launcher {}: {}", name, err)))?;
+                Launcher::new(name.as_str(), template)
+            } else if let Some(ref desktop) = entry.desktop {
+                Launcher::desktop(name.as_str(), desktop.get_ref().as_str())
+            } else if let Some(ref family) = entry.browser {
+                Launcher::browser(name.as_str(), self.browser(&name, family, &entry)?)
+            } else {
+                return Err(Diagnostic {
+                    path: None,
+                    position: self.launcher_table(&name, &entry),
+                    message: format!("launcher {}: missing command, desktop or browser", name),
+                });
+            };
+            let options = [
+                ("executable", entry.executable.as_ref().map(Spanned::start)),
+                ("profile", entry.profile.as_ref().map(Spanned::start)),
+                ("container", entry.container.as_ref().map(Spanned::start)),
+                ("new-instance", entry.new_instance.as_ref().map(Spanned::start)),
+            ];
+            let option = options.iter().find_map(|&(key, start)| start.map(|start| (key, start)));
+            if let (None, Some((key, start))) = (&entry.browser, option) {
+                return Err(Diagnostic {
+                    path: None,
+                    position: Some(Position::at(self.source, start)),
+                    message: format!("launcher {}: {} requires browser", name, key),
+                });
+            }
+            if let Some(detach) = entry.detach {
+                launcher = launcher.with_detach(self.detach(&name, detach)?);
+            }
+            if let Some(ref backend) = entry.backend {
+                launcher = launcher.with_backend(match backend.get_ref().as_str() {
+                    "fork" => Backend::Fork,
+                    "spawn" => Backend::Spawn,
+                    _ => {
+                        return Err(self.error(
+                            backend,
+                            format_args!("launcher {}: backend must be fork or spawn", name),
+                        ))
+                    }
+                });
+            }
+
+            let environment = self.environment(&name, entry.clear_env, entry.unset_env, entry.env)?;
+            launcher = launcher.with_environment(environment);
+            if let Some(ref cwd) = entry.cwd {
+                if !cwd.get_ref().starts_with('/') {
+                    return Err(self.error(cwd, format_args!("launcher {}: cwd must be absolute path", name)));
+                }
+                launcher = launcher.with_cwd(cwd.get_ref());
+            }
+            if let Some(ref umask) = entry.umask {
+                let invalid = |message| self.error(umask, format_args!("launcher {}: {}", name, message));
+                let mask = u32::from_str_radix(umask.get_ref(), 8)
+                    .ok()
+                    .filter(|&mask| mask <= 0o777)
+                    .ok_or_else(|| invalid("umask must be octal number up to 777"))?;
+                if launcher.backend() == Backend::Spawn {
+                    return Err(invalid("umask is not supported by the spawn backend"));
+                }
+                launcher = launcher.with_umask(mask);
+            }
+            if let Some(ref remote) = entry.remote {
+                launcher = launcher.with_remote(self.remote(&name, remote)?);
+            }
+            launchers.insert(name, launcher);
+        }
+        Ok(launchers)
+    }
+
+    fn rule(
+        &self,
+        entry: RuleEntry,
+        launchers: &BTreeMap<String, Launcher>,
+        chooser: Option<&Template>,
+    ) -> Result<Rule, Diagnostic> {
+        let name = entry.name.get_ref();
+        // Desktop entry IDs can be used without defining a launcher
+        let launcher = |reference: &Spanned<String>| match launchers.get(reference.get_ref()) {
+            Some(launcher) => Ok(launcher.clone()),
+            None if reference.get_ref().ends_with(".desktop") => {
+                Ok(Launcher::desktop(reference.get_ref().as_str(), reference.get_ref().as_str()))
+            }
+            None => Err(self.error(
+                reference,
+                format_args!("rule {}: unknown launcher {}", name, reference.get_ref()),
+            )),
+        };
+
+        let rule = match (entry.launcher, entry.choose.split_first()) {
+            (Some(ref primary), None) => {
+                let mut rule = Rule::new(name.as_str(), launcher(primary)?);
+                for fallback in &entry.fallback {
+                    rule = rule.with_fallback(launcher(fallback)?);
+                }
+                rule
+            }
+            (None, Some((first, others))) => {
+                if let Some(fallback) = entry.fallback.first() {
+                    return Err(self.error(
+                        fallback,
+                        format_args!("rule {}: fallback cannot be combined with choose", name),
+                    ));
+                }
+                let menu = chooser.ok_or_else(|| {
+                    self.error(first, format_args!("rule {}: choose requires [chooser] command", name))
+                })?;
+                let mut rule = Rule::new(name.as_str(), launcher(first)?);
+                for other in others {
+                    rule = rule.with_fallback(launcher(other)?);
+                }
+                rule.with_chooser(menu.clone())
+            }
+            (Some(ref primary), Some(_)) => {
+                return Err(self.error(
+                    primary,
+                    format_args!("rule {}: launcher cannot be combined with choose", name),
+                ))
+            }
+            (None, None) => {
+                return Err(self.error(&entry.name, format_args!("rule {}: missing launcher", name)))
+            }
+        };
+        let owner = format!("rule {}", name);
+        let conditions = self.conditions(
+            &owner,
+            entry.scheme,
+            entry.host,
+            entry.path,
+            entry.regex,
+            entry.mime,
+        )?;
+
+        Ok(rule.with_conditions(conditions))
+    }
+
+    fn rewrite(&self, entry: RewriteEntry) -> Result<Rewrite, Diagnostic> {
+        let spanned_name = &entry.name;
+        let name = spanned_name.get_ref();
+        let owner = format!("rewrite {}", name);
+        let invalid = |message: fmt::Arguments| self.error(spanned_name, format_args!("{}: {}", owner, message));
+
+        let mut actions = Vec::new();
+        if let Some(globs) = entry.strip_params {
+            actions.push(Action::strip_params(globs).map_err(|err| invalid(format_args!("{}", err)))?);
+        }
+        if let Some(param) = entry.unwrap {
+            actions.push(Action::Unwrap(param));
+        }
+        if let Some(ref host) = entry.set_host {
+            let action = Action::set_host(host.get_ref().as_str())
+                .map_err(|err| self.error(host, format_args!("{}: invalid host: {}", owner, err)))?;
+            actions.push(action);
+        }
+        let action = match actions.len() {
+            0 => return Err(invalid(format_args!("missing strip-params, unwrap or set-host"))),
+            1 => actions.remove(0),
+            _ => return Err(invalid(format_args!("only one of strip-params, unwrap and set-host allowed"))),
+        };
+
+        let conditions = self.conditions(
+            &owner,
+            entry.scheme,
+            entry.host,
+            entry.path,
+            entry.regex,
+            entry.mime,
+        )?;
+        Ok(Rewrite::new(name.as_str(), action).with_conditions(conditions))
+    }
+
+    fn conditions(
+        &self,
+        owner: &str,
+        scheme: Option<String>,
+        host: Option<Spanned<String>>,
+        path: Option<String>,
+        regex: Option<Spanned<String>>,
+        mime: Option<Spanned<String>>,
+    ) -> Result<Conditions, Diagnostic> {
+        let mut conditions = Conditions::default();
+        if let Some(scheme) = scheme {
+            conditions = conditions.with_scheme(scheme);
+        }
+        if let Some(ref host) = host {
+            let pattern = HostPattern::from_str(host.get_ref())
+                .map_err(|err| self.error(host, format_args!("{}: invalid host: {}", owner, err)))?;
+            conditions = conditions.with_host(pattern);
+        }
+        if let Some(path) = path {
+            conditions = conditions.with_path(path);
+        }
+        if let Some(ref regex) = regex {
+            let regex = Regex::new(regex.get_ref())
+                .map_err(|err| self.error(regex, format_args!("{}: invalid regex: {}", owner, err)))?;
+            conditions = conditions.with_regex(regex);
+        }
+        if let Some(ref mime) = mime {
+            let pattern = MimePattern::from_str(mime.get_ref())
+                .map_err(|err| self.error(mime, format_args!("{}: {}", owner, err)))?;
+            conditions = conditions.with_mime(pattern);
+        }
+
+        Ok(conditions)
+    }
+
+    fn clipboard(&self, entry: ClipboardEntry) -> Result<Vec<String>, Diagnostic> {
+        let invalid =
+            |message: &dyn fmt::Display| self.error(&entry.command, format_args!("clipboard: {}", message));
+        let words = sh::split(entry.command.get_ref()).map_err(|err| invalid(&err))?;
+        let argv = sh::expand(&words).map_err(|err| invalid(&err))?;
+        if argv.is_empty() {
+            return Err(invalid(&"empty command"));
+        }
+        Ok(argv)
+    }
+
+    fn config(&self, file: ConfigFile) -> Result<Config, Diagnostic> {
+        let launchers = self.launchers(file.launchers)?;
+        let chooser = match file.chooser {
+            Some(entry) => Some(
+                entry
+                    .command
+                    .get_ref()
+                    .parse::<Template>()
+                    .map_err(|err| self.error(&entry.command, format_args!("chooser: {}", err)))?,
+            ),
+            None => None,
+        };
+
+        let mut names = HashSet::new();
+        let mut rules = Vec::with_capacity(file.rules.len());
+        for entry in file.rules {
+            if !names.insert(entry.name.get_ref().clone()) {
+                return Err(self.error(
+                    &entry.name,
+                    format_args!("duplicate rule {}", entry.name.get_ref()),
+                ));
+            }
+            rules.push(self.rule(entry, &launchers, chooser.as_ref())?);
+        }
+
+        let mut names = HashSet::new();
+        let mut rewrites = Vec::with_capacity(file.rewrites.len());
+        for entry in file.rewrites {
+            if !names.insert(entry.name.get_ref().clone()) {
+                return Err(self.error(
+                    &entry.name,
+                    format_args!("duplicate rewrite {}", entry.name.get_ref()),
+                ));
+            }
+            rewrites.push(self.rewrite(entry)?);
+        }
+
+        let clipboard = match file.clipboard {
+            Some(entry) => Some(self.clipboard(entry)?),
+            None => None,
+        };
+
+        let mut dispatcher = Dispatcher::new(rules).with_rewriter(Rewriter::new(rewrites));
+        if file.mimeapps {
+            dispatcher = dispatcher.with_mime_apps(MimeApps::load());
+        }
+        Ok(Config { dispatcher, clipboard })
+    }
+}
+
+impl FromStr for Config {
+    type Err = Diagnostic;
+
+    /// Parse and validate configuration file contents.
+    fn from_str(source: &str) -> Result<Config, Diagnostic> {
+        let file: ConfigFile = toml::from_str(source).map_err(|err| {
+            let position = err.line_col().map(|(line, column)| Position {
+                line: line + 1,
+                column: column + 1,
+            });
+            Diagnostic {
+                path: None,
+                position,
+                message: err.to_string(),
+            }
+        })?;
+
+        Validator { source }.config(file)
+    }
+}
+
+impl Config {
+    /// Find the configuration file in the XDG configuration directories.
+    pub fn locate() -> Result<PathBuf, ConfigError> {
+        let search_path = xdg::config_search_path();
+        xdg::find(&search_path, CONFIG_FILE).ok_or_else(|| {
+            ConfigError::NotFound(search_path.iter().map(|dir| dir.join(CONFIG_FILE)).collect())
+        })
+    }
+
+    /// Load configuration from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_owned(), err))?;
+
+        source.parse().map_err(|diagnostic| {
+            ConfigError::Invalid(Diagnostic {
+                path: Some(path.to_owned()),
+                ..diagnostic
+            })
+        })
+    }
+
+    /// Locate and load the configuration file.
+    pub fn load_default() -> Result<Config, ConfigError> {
+        Config::load(Config::locate()?)
+    }
+
+    /// Dispatcher with the configured rules.
+    pub fn dispatcher(&self) -> &Dispatcher {
+        &self.dispatcher
+    }
+
+    /// Command printing the clipboard contents, if configured.
+    pub fn clipboard(&self) -> Option<&[String]> {
+        self.clipboard.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use launcher::Program;
+    use url::Url;
+
+    const EXAMPLE: &str = r#"
+        [launchers.browser]
+        command = "firefox %u"
+
+        [launchers.mail]
+        command = "thunderbird -compose %u"
+
+        [[rules]]
+        name = "mail"
+        scheme = "mailto"
+        launcher = "mail"
+
+        [[rules]]
+        name = "web"
+        host = "*.example.com"
+        launcher = "browser"
+    "#;
+
+    fn diagnostic(source: &str) -> Diagnostic {
+        source.parse::<Config>().unwrap_err()
+    }
+
+    #[test]
+    fn loads_rules_in_order() {
+        let config: Config = EXAMPLE.parse().unwrap();
+        let dispatcher = config.dispatcher();
+        let url = Url::parse("mailto:www.example.com").unwrap();
+
+        let names: Vec<_> = dispatcher.rules().iter().map(Rule::name).collect();
+        assert_eq!(names, ["mail", "web"]);
+        assert_eq!(dispatcher.select(&url).map(Rule::name), Some("mail"));
+        assert_eq!(
+            dispatcher.rules()[1].launcher().argv(&url, None).unwrap(),
+            ["firefox", "mailto:www.example.com"]
+        );
+    }
+
+    #[test]
+    fn loads_detach_options() {
+        let config: Config = "[launchers.x]\ncommand = 'x'\n\
+                              detach = { session = true, output = '/tmp/x.log' }\n\
+                              [[rules]]\nname = 'a'\nlauncher = 'x'\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            config.dispatcher().rules()[0].launcher().detach(),
+            &Detach {
+                new_session: true,
+                output: Output::Log("/tmp/x.log".into()),
+                ..Detach::default()
+            }
+        );
+
+        let invalid = diagnostic("[launchers.x]\ncommand = 'x'\ndetach = { output = 'log' }\n");
+        assert_eq!(invalid.position, Some(Position { line: 3, column: 21 }));
+    }
+
+    #[test]
+    fn loads_backend() {
+        let config: Config = "[launchers.x]\ncommand = 'x'\nbackend = 'spawn'\n\
+                              [[rules]]\nname = 'a'\nlauncher = 'x'\n"
+            .parse()
+            .unwrap();
+        assert_eq!(config.dispatcher().rules()[0].launcher().backend(), Backend::Spawn);
+
+        let invalid = diagnostic("[launchers.x]\ncommand = 'x'\nbackend = 'vfork'\n");
+        assert_eq!(invalid.position, Some(Position { line: 3, column: 11 }));
+    }
+
+    #[test]
+    fn loads_desktop_launchers() {
+        let config: Config = "[launchers.web]\ndesktop = 'firefox.desktop'\n\
+                              [[rules]]\nname = 'a'\nlauncher = 'web'\nfallback = ['chromium.desktop']\n"
+            .parse()
+            .unwrap();
+        let ids: Vec<_> = config.dispatcher().rules()[0]
+            .launchers()
+            .map(|launcher| match *launcher.program() {
+                Program::Desktop(ref id) => id.as_str(),
+                ref other => panic!("unexpected program: {:?}", other),
+            })
+            .collect();
+        assert_eq!(ids, ["firefox.desktop", "chromium.desktop"]);
+
+        let both = diagnostic("[launchers.x]\ncommand = 'x'\ndesktop = 'x.desktop'\n");
+        assert_eq!(both.position, Some(Position { line: 3, column: 11 }));
+        let neither = diagnostic("[launchers.x]\nbackend = 'spawn'\n");
+        assert_eq!(neither.message, "launcher x: missing command, desktop or browser");
+        assert_eq!(neither.position, Some(Position { line: 1, column: 1 }));
+        let inline = diagnostic("[launchers]\nx = { cwd = '/' }\n");
+        assert_eq!(inline.position, Some(Position { line: 2, column: 13 }));
+    }
+
+    #[test]
+    fn loads_browser_launchers() {
+        let config: Config = "[launchers.work]\nbrowser = 'firefox'\nprofile = 'work'\ncontainer = 'Corp'\n\
+                              new-instance = true\n\
+                              [launchers.personal]\nbrowser = 'chromium'\nexecutable = 'google-chrome'\n\
+                              [[rules]]\nname = 'corp'\nhost = '.corp.example.com'\nlauncher = 'work'\n\
+                              [[rules]]\nname = 'web'\nlauncher = 'personal'\n"
+            .parse()
+            .unwrap();
+        let browsers: Vec<_> = config
+            .dispatcher()
+            .rules()
+            .iter()
+            .flat_map(Rule::launchers)
+            .map(|launcher| match *launcher.program() {
+                Program::Browser(ref browser) => browser.clone(),
+                ref other => panic!("unexpected program: {:?}", other),
+            })
+            .collect();
+        let work = Browser::new(Family::Firefox)
+            .with_profile("work")
+            .with_container("Corp")
+            .with_new_instance(true);
+        assert_eq!(browsers, [work, Browser::new(Family::Chromium).with_executable("google-chrome")]);
+
+        let family = diagnostic("[launchers.x]\nbrowser = 'opera'\n");
+        assert_eq!(family.message, "launcher x: browser must be firefox or chromium");
+        let container = diagnostic("[launchers.x]\nbrowser = 'chromium'\ncontainer = 'Corp'\n");
+        assert_eq!(container.position, Some(Position { line: 3, column: 13 }));
+        assert_eq!(container.message, "launcher x: container requires firefox");
+        let profile = diagnostic("[launchers.x]\ncommand = 'x'\nprofile = 'work'\n");
+        assert_eq!(profile.message, "launcher x: profile requires browser");
+        let both = diagnostic("[launchers.x]\ndesktop = 'x.desktop'\nbrowser = 'firefox'\n");
+        assert_eq!(both.position.map(|p| p.line), Some(3));
+    }
+
+    #[test]
+    fn loads_environment() {
+        let config: Config = "[launchers.x]\ncommand = 'x'\nunset-env = ['LD_PRELOAD']\n\
+                              env = { MOZ_ENABLE_WAYLAND = '1' }\ncwd = '/tmp'\numask = '027'\n\
+                              [[rules]]\nname = 'a'\nlauncher = 'x'\n"
+            .parse()
+            .unwrap();
+        let launcher = config.dispatcher().rules()[0].launchers().next().unwrap();
+        assert!(!launcher.environment().clear);
+        assert_eq!(launcher.environment().unset, ["LD_PRELOAD"]);
+        assert_eq!(launcher.environment().set["MOZ_ENABLE_WAYLAND"], "1");
+        assert_eq!(launcher.cwd(), Some(Path::new("/tmp")));
+        assert_eq!(launcher.umask(), Some(0o027));
+
+        let name = diagnostic("[launchers.x]\ncommand = 'x'\nunset-env = ['A=B']\n");
+        assert_eq!(name.position, Some(Position { line: 3, column: 14 }));
+        let cwd = diagnostic("[launchers.x]\ncommand = 'x'\ncwd = 'tmp'\n");
+        assert_eq!(cwd.message, "launcher x: cwd must be absolute path");
+        let umask = diagnostic("[launchers.x]\ncommand = 'x'\numask = '0o77'\n");
+        assert_eq!(umask.message, "launcher x: umask must be octal number up to 777");
+        let spawn = diagnostic("[launchers.x]\ncommand = 'x'\nbackend = 'spawn'\numask = '077'\n");
+        assert_eq!(spawn.message, "launcher x: umask is not supported by the spawn backend");
+    }
+
+    #[test]
+    fn loads_remote() {
+        let config: Config = "[launchers.x]\ncommand = 'emacs %f'\n\
+                              remote = { process = 'emacs', command = 'emacsclient -n %f' }\n\
+                              [[rules]]\nname = 'a'\nlauncher = 'x'\n"
+            .parse()
+            .unwrap();
+        let remote = config.dispatcher().rules()[0].launcher().remote().unwrap();
+        assert_eq!(remote.process(), "emacs");
+        assert_eq!(remote.template().to_string(), "emacsclient -n %f");
+
+        let process = diagnostic("[launchers.x]\ncommand = 'x'\nremote = { process = 'bin/x', command = 'x' }\n");
+        assert_eq!(process.message, "launcher x: remote process must be name or absolute path");
+        assert_eq!(process.position, Some(Position { line: 3, column: 22 }));
+        let command = diagnostic("[launchers.x]\ncommand = 'x'\nremote = { process = 'x', command = 'x %z' }\n");
+        assert_eq!(command.message, "launcher x: remote: unknown placeholder: %z");
+        let missing = diagnostic("[launchers.x]\ncommand = 'x'\nremote = { command = 'x' }\n");
+        assert!(missing.message.contains("process"), "{}", missing.message);
+    }
+
+    #[test]
+    fn loads_fallbacks() {
+        let config: Config = "[launchers.x]\ncommand = 'x'\n[launchers.y]\ncommand = 'y'\n\
+                              [[rules]]\nname = 'a'\nlauncher = 'x'\nfallback = ['y', 'x']\n"
+            .parse()
+            .unwrap();
+        let names: Vec<_> = config.dispatcher().rules()[0].launchers().map(Launcher::name).collect();
+        assert_eq!(names, ["x", "y", "x"]);
+
+        let unknown = diagnostic(
+            "[launchers.x]\ncommand = 'x'\n\
+             [[rules]]\nname = 'a'\nlauncher = 'x'\nfallback = ['x', 'z']\n",
+        );
+        assert_eq!(unknown.position, Some(Position { line: 6, column: 18 }));
+        assert_eq!(unknown.message, "rule a: unknown launcher z");
+    }
+
+    #[test]
+    fn default_handlers_are_optional() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let config: Config = EXAMPLE.parse().unwrap();
+        assert!(config.dispatcher().default_rule(&url).is_none());
+
+        assert!("mimeapps = true\n".parse::<Config>().is_ok());
+        assert!("mimeapps = 'yes'\n".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn loads_chooser() {
+        let config: Config = "[chooser]\ncommand = 'dmenu -p %u'\n\
+                              [launchers.x]\ncommand = 'x'\n[launchers.y]\ncommand = 'y'\n\
+                              [[rules]]\nname = 'a'\nchoose = ['y', 'x']\n"
+            .parse()
+            .unwrap();
+        let rule = &config.dispatcher().rules()[0];
+        assert_eq!(rule.chooser().map(ToString::to_string), Some("dmenu -p %u".into()));
+        let names: Vec<_> = rule.launchers().map(Launcher::name).collect();
+        assert_eq!(names, ["y", "x"]);
+
+        let menu = diagnostic("[launchers.x]\ncommand = 'x'\n[[rules]]\nname = 'a'\nchoose = ['x']\n");
+        assert_eq!(menu.message, "rule a: choose requires [chooser] command");
+        assert_eq!(menu.position, Some(Position { line: 5, column: 11 }));
+
+        let both = diagnostic(
+            "[chooser]\ncommand = 'dmenu'\n[launchers.x]\ncommand = 'x'\n\
+             [[rules]]\nname = 'a'\nlauncher = 'x'\nchoose = ['x']\n",
+        );
+        assert_eq!(both.message, "rule a: launcher cannot be combined with choose");
+        let neither = diagnostic("[[rules]]\nname = 'a'\n");
+        assert_eq!(neither.message, "rule a: missing launcher");
+    }
+
+    #[test]
+    fn loads_clipboard() {
+        let config: Config = "[clipboard]\ncommand = 'xsel --output --clipboard'\n".parse().unwrap();
+        assert_eq!(config.clipboard().unwrap(), ["xsel", "--output", "--clipboard"]);
+        assert!(EXAMPLE.parse::<Config>().unwrap().clipboard().is_none());
+
+        let empty = diagnostic("[clipboard]\ncommand = ' '\n");
+        assert_eq!(empty.message, "clipboard: empty command");
+        assert_eq!(empty.position, Some(Position { line: 2, column: 11 }));
+        let quote = diagnostic("[clipboard]\ncommand = \"xclip 'x\"\n");
+        assert_eq!(quote.position.map(|p| p.line), Some(2));
+    }
+
+    #[test]
+    fn loads_rewrites() {
+        let config: Config = r#"
+            [[rewrites]]
+            name = "tracking"
+            strip-params = ["utm_*"]
+
+            [[rewrites]]
+            name = "nitter"
+            host = ".twitter.com"
+            set-host = "nitter.net"
+        "#
+        .parse()
+        .unwrap();
+        let url = Url::parse("https://twitter.com/a?utm_source=x").unwrap();
+        let rewritten = config.dispatcher().rewriter().rewrite(&url).unwrap();
+        assert_eq!(rewritten.url.as_str(), "https://nitter.net/a");
+
+        let missing = diagnostic("[[rewrites]]\nname = 'a'\nhost = 'x'\n");
+        assert_eq!(missing.message, "rewrite a: missing strip-params, unwrap or set-host");
+        assert_eq!(missing.position, Some(Position { line: 2, column: 8 }));
+        let both = diagnostic("[[rewrites]]\nname = 'a'\nunwrap = 'q'\nset-host = 'x'\n");
+        assert_eq!(both.message, "rewrite a: only one of strip-params, unwrap and set-host allowed");
+        let host = diagnostic("[[rewrites]]\nname = 'a'\nset-host = 'a b'\n");
+        assert_eq!(host.position, Some(Position { line: 3, column: 12 }));
+    }
+
+    #[test]
+    fn reports_positions() {
+        let unknown = diagnostic("[[rules]]\nname = 'a'\nlauncher = 'nope'\n");
+        assert_eq!(unknown.position, Some(Position { line: 3, column: 12 }));
+        assert_eq!(unknown.message, "rule a: unknown launcher nope");
+
+        let template = diagnostic("[launchers.x]\n  command = \"a 'b\"");
+        assert_eq!(template.position, Some(Position { line: 2, column: 13 }));
+
+        let syntax = diagnostic("[launchers.x]\ncommand = \n");
+        assert_eq!(syntax.position.map(|p| p.line), Some(2));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let duplicate = diagnostic(
+            "[launchers.x]\ncommand = 'x'\n\
+             [[rules]]\nname = 'a'\nlauncher = 'x'\n\
+             [[rules]]\nname = 'a'\nlauncher = 'x'\n",
+        );
+        assert_eq!(duplicate.message, "duplicate rule a");
+        assert_eq!(duplicate.position.map(|p| p.line), Some(7));
+
+        let regex = diagnostic(
+            "[launchers.x]\ncommand = 'x'\n[[rules]]\nname = 'a'\nregex = '('\nlauncher = 'x'\n",
+        );
+        assert_eq!(regex.position.map(|p| p.line), Some(5));
+
+        let mime = diagnostic("[launchers.x]\ncommand = 'x'\n[[rules]]\nname = 'a'\nmime = 'pdf'\nlauncher = 'x'\n");
+        assert_eq!(mime.position, Some(Position { line: 5, column: 8 }));
+        assert_eq!(mime.message, "rule a: invalid MIME type pattern: pdf");
+
+        let unknown = diagnostic("[[rules]]\nname = 'a'\nlauncher = 'x'\nport = 80\n");
+        assert!(unknown.message.contains("port"), "{}", unknown.message);
+    }
+}
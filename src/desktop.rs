@@ -0,0 +1,431 @@
+//! Desktop entries, as defined by the freedesktop.org Desktop Entry Specification.
+//!
+//! Only the parts needed for launching applications are supported:
+//! the `Exec` key with its field codes, and the `Name` and `Icon` keys it refers to.
+
+use std::path::{Path, PathBuf};
+use std::{error, fmt, fs, io};
+
+use url::Url;
+
+use xdg;
+
+/// Reasons for an unusable desktop entry.
+#[derive(Debug)]
+pub enum DesktopError {
+    /// No desktop entry with the ID exists.
+    NotFound(String),
+    /// The desktop entry could not be read.
+    Io(PathBuf, io::Error),
+    /// The desktop entry is malformed; the description of the problem is included.
+    Invalid(PathBuf, String),
+    /// `%f` or `%F` used with an URL that does not refer to a local file.
+    NotLocalFile(String),
+}
+
+impl fmt::Display for DesktopError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DesktopError::NotFound(ref id) => write!(f, "desktop entry not found: {}", id),
+            DesktopError::Io(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+            DesktopError::Invalid(ref path, ref message) => write!(f, "{}: {}", path.display(), message),
+            DesktopError::NotLocalFile(ref url) => write!(f, "not a local file: {}", url),
+        }
+    }
+}
+
+impl error::Error for DesktopError {}
+
+/// Undo escaping of a string value.
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Split the (unescaped) `Exec` value into arguments.
+///
+/// Arguments are separated by spaces; an argument can be quoted by double quotes,
+/// inside of which `"`, `` ` ``, `$` and `\` must be escaped by a backslash.
+fn split_exec(exec: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = exec.chars().peekable();
+
+    loop {
+        while chars.peek() == Some(&' ') {
+            chars.next();
+        }
+        let first = match chars.next() {
+            Some(c) => c,
+            None => return Ok(args),
+        };
+
+        let mut arg = String::new();
+        if first == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c @ '"') | Some(c @ '`') | Some(c @ '$') | Some(c @ '\\') => arg.push(c),
+                        _ => return Err("invalid escape in quoted Exec argument".into()),
+                    },
+                    Some(c) => arg.push(c),
+                    None => return Err("unterminated quote in Exec".into()),
+                }
+            }
+        } else {
+            arg.push(first);
+            while let Some(&c) = chars.peek() {
+                if c == ' ' {
+                    break;
+                }
+                arg.push(c);
+                chars.next();
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Local file path of the URL.
+fn file_path(url: &Url) -> Result<String, DesktopError> {
+    url.to_file_path()
+        .map(|path| path.to_string_lossy().into_owned())
+        .map_err(|_| DesktopError::NotLocalFile(url.to_string()))
+}
+
+/// Find the desktop file with the ID in the directory.
+///
+/// The ID of a file in a subdirectory has the `/` replaced by `-`,
+/// so every dash may be a directory separator.
+fn locate(dir: &Path, id: &str) -> Option<PathBuf> {
+    let direct = dir.join(id);
+    if direct.is_file() {
+        return Some(direct);
+    }
+
+    id.match_indices('-').find_map(|(dash, _)| {
+        let subdir = dir.join(&id[..dash]);
+        if subdir.is_dir() {
+            locate(&subdir, &id[dash + 1..])
+        } else {
+            None
+        }
+    })
+}
+
+/// Application desktop entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DesktopEntry {
+    id: String,
+    path: PathBuf,
+    name: Option<String>,
+    icon: Option<String>,
+    exec: Vec<String>,
+}
+
+impl DesktopEntry {
+    /// Parse the contents of a desktop file.
+    pub fn parse<S, P>(id: S, path: P, source: &str) -> Result<DesktopEntry, DesktopError>
+    where
+        S: Into<String>,
+        P: Into<PathBuf>,
+    {
+        let (id, path) = (id.into(), path.into());
+        let invalid = |message: String| DesktopError::Invalid(path.clone(), message);
+
+        let mut group = None;
+        let mut found = false;
+        let (mut kind, mut hidden, mut name, mut icon, mut exec) = (None, false, None, None, None);
+        for (n, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                group = Some(&line[1..line.len() - 1]);
+                found |= group == Some("Desktop Entry");
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("line {}: expected key=value", n + 1)))?;
+            if group != Some("Desktop Entry") {
+                continue;
+            }
+
+            // Localized keys (Name[de]) are ignored along with everything unknown
+            let value = unescape(value.trim());
+            match key.trim() {
+                "Type" => kind = Some(value),
+                "Hidden" => hidden = value == "true",
+                "Name" => name = Some(value),
+                "Icon" => icon = Some(value).filter(|icon| !icon.is_empty()),
+                "Exec" => exec = Some(split_exec(&value).map_err(&invalid)?),
+                _ => {}
+            }
+        }
+
+        if !found {
+            return Err(invalid("missing [Desktop Entry] group".into()));
+        }
+        if hidden {
+            return Err(DesktopError::NotFound(id));
+        }
+        if kind.as_ref().is_some_and(|kind| kind != "Application") {
+            return Err(invalid("not an application".into()));
+        }
+        let exec = exec
+            .filter(|exec| !exec.is_empty())
+            .ok_or_else(|| invalid("missing Exec".into()))?;
+
+        Ok(DesktopEntry {
+            id,
+            path,
+            name,
+            icon,
+            exec,
+        })
+    }
+
+    /// Read a desktop file.
+    pub fn load<S: Into<String>, P: AsRef<Path>>(id: S, path: P) -> Result<DesktopEntry, DesktopError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|err| DesktopError::Io(path.into(), err))?;
+        DesktopEntry::parse(id, path, &source)
+    }
+
+    /// Find the desktop entry by its ID in the `applications` subdirectories
+    /// of the XDG data directories.
+    pub fn find(id: &str) -> Result<DesktopEntry, DesktopError> {
+        xdg::data_search_path()
+            .iter()
+            .find_map(|dir| locate(&dir.join("applications"), id))
+            .ok_or_else(|| DesktopError::NotFound(id.into()))
+            .and_then(|path| DesktopEntry::load(id, path))
+    }
+
+    /// Desktop entry ID (file name, with subdirectories separated by `-`).
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Location of the desktop file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Name of the application.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Arguments of the `Exec` key, with unexpanded field codes.
+    pub fn exec(&self) -> &[String] {
+        &self.exec
+    }
+
+    /// Check if the entry opens multiple URLs at once, with `%U` or `%F`.
+    pub fn accepts_multiple(&self) -> bool {
+        self.exec.iter().any(|arg| arg == "%U" || arg == "%F")
+    }
+
+    /// Expand field codes in a single argument.
+    fn expand(&self, arg: &str, urls: &[Url], targets: &mut bool) -> Result<String, DesktopError> {
+        let mut expanded = String::with_capacity(arg.len());
+        let mut chars = arg.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expanded.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('u') => {
+                    *targets = true;
+                    expanded.extend(urls.first().map(Url::as_str));
+                }
+                Some('f') => {
+                    *targets = true;
+                    if let Some(url) = urls.first() {
+                        expanded.push_str(&file_path(url)?);
+                    }
+                }
+                Some('c') => expanded.extend(self.name()),
+                Some('k') => expanded.push_str(&self.path.to_string_lossy()),
+                Some('%') => expanded.push('%'),
+                // Deprecated
+                Some('d') | Some('D') | Some('n') | Some('N') | Some('v') | Some('m') => {}
+                Some(code) => {
+                    return Err(DesktopError::Invalid(
+                        self.path.clone(),
+                        format!("invalid field code in Exec: %{}", code),
+                    ))
+                }
+                None => return Err(DesktopError::Invalid(self.path.clone(), "incomplete field code in Exec".into())),
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Produce an argument vector for opening the URLs.
+    ///
+    /// `%u` and `%f` receive only the first URL; `%U` and `%F` receive all of them.
+    /// If `Exec` does not mention any of these, the URLs are appended, as GLib does.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate urldispatch;
+    /// use urldispatch::desktop::DesktopEntry;
+    /// let source = "[Desktop Entry]\nType=Application\nIcon=web\nExec=browser %i --new-window %U\n";
+    /// let entry = DesktopEntry::parse("browser.desktop", "/browser.desktop", source).unwrap();
+    /// let url = url::Url::parse("https://example.com").unwrap();
+    /// assert_eq!(
+    ///     entry.argv(&[url]).unwrap(),
+    ///     ["browser", "--icon", "web", "--new-window", "https://example.com/"]
+    /// );
+    /// ```
+    pub fn argv(&self, urls: &[Url]) -> Result<Vec<String>, DesktopError> {
+        let mut argv = Vec::with_capacity(self.exec.len() + urls.len());
+        let mut targets = false;
+
+        for arg in &self.exec {
+            match arg.as_str() {
+                "%U" => {
+                    targets = true;
+                    argv.extend(urls.iter().map(Url::to_string));
+                }
+                "%F" => {
+                    targets = true;
+                    for url in urls {
+                        argv.push(file_path(url)?);
+                    }
+                }
+                "%i" => {
+                    if let Some(ref icon) = self.icon {
+                        argv.push("--icon".into());
+                        argv.push(icon.clone());
+                    }
+                }
+                // Field code without a value, such as %u without any URL
+                "%u" | "%f" if urls.is_empty() => targets = true,
+                // Deprecated, to be removed along with the argument
+                "%d" | "%D" | "%n" | "%N" | "%v" | "%m" => {}
+                _ => argv.push(self.expand(arg, urls, &mut targets)?),
+            }
+        }
+        if !targets {
+            argv.extend(urls.iter().map(Url::to_string));
+        }
+
+        Ok(argv)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{env, process};
+
+    fn entry(exec: &str) -> DesktopEntry {
+        let source = format!(
+            "# comment\n[Desktop Entry]\nType=Application\nName=Web\nName[de]=Netz\n\
+             Icon=web-browser\nExec={}\n\n[Desktop Action new]\nExec=other\n",
+            exec
+        );
+        DesktopEntry::parse("web.desktop", "/apps/web.desktop", &source).unwrap()
+    }
+
+    fn urls(inputs: &[&str]) -> Vec<Url> {
+        inputs.iter().map(|input| Url::parse(input).unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_exec() {
+        let web = entry(r#"web "a \\"quoted\\" arg" "\\$HOME" "x\sy" %u"#);
+        assert_eq!(web.name(), Some("Web"));
+        assert_eq!(web.exec(), ["web", "a \"quoted\" arg", "$HOME", "x y", "%u"]);
+
+        let parse = |source: &str| DesktopEntry::parse("x.desktop", "/x.desktop", source);
+        match parse("[Desktop Entry]\nExec=web \"unterminated\n") {
+            Err(DesktopError::Invalid(_, ref message)) => assert_eq!(message, "unterminated quote in Exec"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("[Desktop Entry]\nName=No command\n") {
+            Err(DesktopError::Invalid(_, ref message)) => assert_eq!(message, "missing Exec"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("[Desktop Entry]\nType=Link\nExec=web\n") {
+            Err(DesktopError::Invalid(_, ref message)) => assert_eq!(message, "not an application"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse("[Desktop Entry]\nExec=web\nHidden=true\n") {
+            Err(DesktopError::NotFound(ref id)) => assert_eq!(id, "x.desktop"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn expands_field_codes() {
+        let two = urls(&["file:///tmp/a%20b", "https://example.com/"]);
+
+        assert_eq!(entry("web %u").argv(&two).unwrap(), ["web", "file:///tmp/a%20b"]);
+        assert_eq!(
+            entry("web %U").argv(&two).unwrap(),
+            ["web", "file:///tmp/a%20b", "https://example.com/"]
+        );
+        assert_eq!(entry("web --file=%f").argv(&two[..1]).unwrap(), ["web", "--file=/tmp/a b"]);
+        assert_eq!(
+            entry("web %i --title %c %k 100%%").argv(&[]).unwrap(),
+            ["web", "--icon", "web-browser", "--title", "Web", "/apps/web.desktop", "100%"]
+        );
+        assert_eq!(entry("web %u %d").argv(&[]).unwrap(), ["web"]);
+        assert_eq!(entry("web --dir=%d %U").argv(&two[1..]).unwrap(), ["web", "--dir=", "https://example.com/"]);
+        assert_eq!(entry("web").argv(&two[1..]).unwrap(), ["web", "https://example.com/"]);
+        assert!(entry("web %U").accepts_multiple());
+        assert!(!entry("web --file=%F").accepts_multiple());
+
+        match entry("web %F").argv(&two) {
+            Err(DesktopError::NotLocalFile(ref url)) => assert_eq!(url, "https://example.com/"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match entry("web %x").argv(&two) {
+            Err(DesktopError::Invalid(_, ref message)) => assert_eq!(message, "invalid field code in Exec: %x"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn locates_entries_in_subdirectories() {
+        let applications = env::temp_dir().join(format!("urldispatch-desktop-{}", process::id()));
+        fs::create_dir_all(applications.join("kde")).unwrap();
+        fs::write(applications.join("web.desktop"), "").unwrap();
+        fs::write(applications.join("kde/web-browser.desktop"), "").unwrap();
+
+        assert_eq!(locate(&applications, "web.desktop"), Some(applications.join("web.desktop")));
+        assert_eq!(
+            locate(&applications, "kde-web-browser.desktop"),
+            Some(applications.join("kde/web-browser.desktop"))
+        );
+        assert_eq!(locate(&applications, "gnome-web.desktop"), None);
+
+        fs::remove_dir_all(&applications).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+//! Reasons for failed launch of a command.
+
+use std::ffi::OsString;
+use std::{error, fmt, io};
+
+use nix::errno::Errno;
+use nix::sys::signal::Signal;
+
+/// Failure to launch a command.
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The program does not exist.
+    NotFound(OsString),
+    /// The program cannot be executed by the current user.
+    PermissionDenied(OsString),
+    /// The program could not be executed for other reason.
+    Exec(OsString, Errno),
+    /// The helper process could not be created.
+    Fork(::nix::Error),
+    /// The helper process could not detach from the caller;
+    /// the failed operation (or file) is included.
+    Detach(OsString, Errno),
+    /// The helper process was killed by a signal before reporting the result.
+    Signaled(Signal),
+    /// Other error reported by the operating system.
+    Os(Errno),
+    /// Failure without any further information.
+    Unknown,
+}
+
+impl Error {
+    /// Classify failure to execute a program.
+    pub fn exec<P: Into<OsString>>(program: P, errno: Errno) -> Error {
+        match errno {
+            Errno::ENOENT => Error::NotFound(program.into()),
+            Errno::EACCES => Error::PermissionDenied(program.into()),
+            Errno::UnknownErrno => Error::Unknown,
+            errno => Error::Exec(program.into(), errno),
+        }
+    }
+
+    /// The underlying `errno` value, if there is one.
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match *self {
+            Error::NotFound(_) => Some(Errno::ENOENT as i32),
+            Error::PermissionDenied(_) => Some(Errno::EACCES as i32),
+            Error::Exec(_, errno) | Error::Detach(_, errno) | Error::Os(errno) => Some(errno as i32),
+            Error::Fork(::nix::Error::Sys(errno)) => Some(errno as i32),
+            _ => None,
+        }
+    }
+}
+
+impl From<::nix::Error> for Error {
+    fn from(error: ::nix::Error) -> Error {
+        match error {
+            ::nix::Error::Sys(Errno::UnknownErrno) => Error::Unknown,
+            ::nix::Error::Sys(errno) => Error::Os(errno),
+            _ => Error::Unknown,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        error.raw_os_error().map_or(Error::Unknown, |errno| Error::Os(Errno::from_i32(errno)))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NotFound(ref program) => {
+                write!(f, "{}: command not found", program.to_string_lossy())
+            }
+            Error::PermissionDenied(ref program) => {
+                write!(f, "{}: permission denied", program.to_string_lossy())
+            }
+            Error::Exec(ref program, errno) => {
+                write!(f, "{}: {}", program.to_string_lossy(), errno.desc())
+            }
+            Error::Fork(ref err) => write!(f, "fork failed: {}", err),
+            Error::Detach(ref subject, errno) => {
+                write!(f, "cannot detach: {}: {}", subject.to_string_lossy(), errno.desc())
+            }
+            Error::Signaled(signal) => write!(f, "helper process killed by signal {:?}", signal),
+            Error::Os(errno) => f.write_str(errno.desc()),
+            Error::Unknown => f.write_str("unknown failure"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Fork(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
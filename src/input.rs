@@ -0,0 +1,319 @@
+//! Interpretation of the URLs to open, as given by the user:
+//! on the command line, in a stream, in free text or in the clipboard.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::{env, error, fmt, process};
+
+use regex::Regex;
+use url::{self, Url};
+
+use sh;
+
+/// Start of an URL in free text: a scheme with authority, `mailto:`, or `www.`.
+const URL_START: &str = r"(?i)\b(?:[a-z][a-z0-9+.-]*://|mailto:|www\.)[^\s<>\x22`]+";
+
+/// Separator of URLs in a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Separator {
+    /// One URL per line; surrounding whitespace is ignored.
+    Newline,
+    /// URLs terminated by NUL bytes, as printed by `find -print0`.
+    Nul,
+}
+
+/// Reasons for an input that cannot be opened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    /// The input is not a valid URL.
+    Url(url::ParseError),
+    /// The input has a scheme, but is not valid UTF-8; only paths can be.
+    NotUnicode,
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InputError::Url(ref err) => err.fmt(f),
+            InputError::NotUnicode => f.write_str("URL is not valid UTF-8"),
+        }
+    }
+}
+
+impl error::Error for InputError {}
+
+impl From<url::ParseError> for InputError {
+    fn from(err: url::ParseError) -> InputError {
+        InputError::Url(err)
+    }
+}
+
+/// Reasons for failed reading of the clipboard.
+#[derive(Debug)]
+pub enum ClipboardError {
+    /// The clipboard command could not be started.
+    Launch(::Error),
+    /// The clipboard command failed, likely because the clipboard is empty.
+    Failed(process::ExitStatus),
+    /// The clipboard contents are not valid UTF-8.
+    NotUnicode,
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClipboardError::Launch(ref err) => write!(f, "clipboard: {}", err),
+            ClipboardError::Failed(status) => write!(f, "clipboard: command failed: {}", status),
+            ClipboardError::NotUnicode => f.write_str("clipboard: contents are not valid UTF-8"),
+        }
+    }
+}
+
+impl error::Error for ClipboardError {}
+
+/// Parse the input as an URL or, if it has no scheme, as a path to a local file.
+///
+/// Relative paths are resolved against the working directory;
+/// the file does not need to exist.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::input;
+/// assert_eq!(input::parse("https://example.com").unwrap().as_str(), "https://example.com/");
+/// assert_eq!(input::parse("/tmp/a b.pdf").unwrap().as_str(), "file:///tmp/a%20b.pdf");
+/// ```
+pub fn parse(input: &str) -> Result<Url, url::ParseError> {
+    match Url::parse(input) {
+        Err(url::ParseError::RelativeUrlWithoutBase) if !input.is_empty() => file_url(Path::new(input)),
+        result => result,
+    }
+}
+
+/// URL of the local file; relative paths are resolved against the working directory.
+fn file_url(path: &Path) -> Result<Url, url::ParseError> {
+    let absolute = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        env::current_dir()
+            .map_err(|_| url::ParseError::RelativeUrlWithoutBase)?
+            .join(path)
+    };
+    Url::from_file_path(absolute).map_err(|()| url::ParseError::RelativeUrlWithoutBase)
+}
+
+/// Check if the input starts with an URL scheme and colon.
+fn has_scheme(input: &[u8]) -> bool {
+    match input.iter().position(|&byte| byte == b':') {
+        Some(colon) => {
+            let scheme = &input[..colon];
+            scheme.first().is_some_and(u8::is_ascii_alphabetic)
+                && scheme
+                    .iter()
+                    .all(|&byte| byte.is_ascii_alphanumeric() || b"+.-".contains(&byte))
+        }
+        None => false,
+    }
+}
+
+/// Parse the input like [`parse`](fn.parse.html), allowing paths that are not valid UTF-8.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use std::ffi::OsStr;
+/// use std::os::unix::ffi::OsStrExt;
+/// use urldispatch::input;
+/// let path = OsStr::from_bytes(b"/tmp/\xff.txt");
+/// assert_eq!(input::parse_os(path).unwrap().as_str(), "file:///tmp/%FF.txt");
+/// ```
+pub fn parse_os(input: &OsStr) -> Result<Url, InputError> {
+    match input.to_str() {
+        Some(input) => Ok(parse(input)?),
+        None if has_scheme(input.as_bytes()) => Err(InputError::NotUnicode),
+        None => Ok(file_url(Path::new(input))?),
+    }
+}
+
+/// Split the URLs in a stream; empty entries are skipped.
+///
+/// Entries need not be valid UTF-8, as paths of local files may not be;
+/// see [`parse_os`](fn.parse_os.html).
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::input::{self, Separator};
+/// assert_eq!(input::split(b" a \r\n\nb\n", Separator::Newline), ["a", "b"]);
+/// assert_eq!(input::split(b"a b\0c\0", Separator::Nul), ["a b", "c"]);
+/// ```
+pub fn split(stream: &[u8], separator: Separator) -> Vec<&OsStr> {
+    let entries: Vec<_> = match separator {
+        Separator::Newline => stream.split(|&byte| byte == b'\n').map(<[u8]>::trim_ascii).collect(),
+        Separator::Nul => stream.split(|&byte| byte == 0).collect(),
+    };
+    entries
+        .into_iter()
+        .filter(|entry| !entry.is_empty())
+        .map(OsStr::from_bytes)
+        .collect()
+}
+
+/// Remove trailing punctuation that is likely not a part of the URL,
+/// including closing brackets without an opening one.
+fn trim_punctuation(candidate: &str) -> &str {
+    let mut url = candidate;
+    while let Some(last) = url.chars().last() {
+        let opening = match last {
+            ')' => '(',
+            ']' => '[',
+            '}' => '{',
+            '.' | ',' | ';' | ':' | '!' | '?' | '\'' => {
+                url = &url[..url.len() - 1];
+                continue;
+            }
+            _ => break,
+        };
+        if url.matches(last).count() <= url.matches(opening).count() {
+            break;
+        }
+        url = &url[..url.len() - 1];
+    }
+    url
+}
+
+/// Find the URLs in free text, such as a selected paragraph or terminal output.
+///
+/// URLs are recognized by their scheme followed by `://`, by `mailto:`,
+/// or by the `www.` prefix, in which case `http://` is prepended;
+/// trailing punctuation and unbalanced closing brackets are not included.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::input;
+/// let text = "See https://example.com/a_(b), or (www.example.org).";
+/// assert_eq!(input::extract(text), ["https://example.com/a_(b)", "http://www.example.org"]);
+/// ```
+pub fn extract(text: &str) -> Vec<String> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN
+        .get_or_init(|| Regex::new(URL_START).expect("valid URL pattern"))
+        .find_iter(text)
+        .map(|found| trim_punctuation(found.as_str()))
+        .map(|url| match url.get(..4) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("www.") => format!("http://{}", url),
+            _ => url.to_owned(),
+        })
+        .filter(|url| Url::parse(url).is_ok_and(|url| url.has_host() || url.scheme() == "mailto"))
+        .collect()
+}
+
+/// Command printing the clipboard contents, unless configured otherwise:
+/// `wl-paste` under Wayland, `xclip` elsewhere.
+pub fn default_clipboard() -> Vec<String> {
+    let command: &[&str] = match env::var_os("WAYLAND_DISPLAY") {
+        Some(ref display) if !display.is_empty() => &["wl-paste", "--no-newline"],
+        _ => &["xclip", "-out", "-selection", "clipboard"],
+    };
+    command.iter().map(|&word| word.to_owned()).collect()
+}
+
+/// Read the clipboard contents with the command, such as [`default_clipboard`](fn.default_clipboard.html).
+pub fn clipboard(argv: &[String]) -> Result<String, ClipboardError> {
+    let command = sh::command(argv).ok_or(ClipboardError::Launch(::Error::Unknown))?;
+    let output = sh::capture(command, b"").map_err(ClipboardError::Launch)?;
+    if !output.status.success() {
+        return Err(ClipboardError::Failed(output.status));
+    }
+    String::from_utf8(output.stdout).map_err(|_| ClipboardError::NotUnicode)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_paths() {
+        assert_eq!(parse("mailto:user@example.com").unwrap().scheme(), "mailto");
+        assert_eq!(parse("file:///etc/hosts").unwrap().path(), "/etc/hosts");
+        assert_eq!(parse("/etc/hosts").unwrap().as_str(), "file:///etc/hosts");
+
+        let relative = parse("docs/read me.txt").unwrap();
+        assert_eq!(relative.to_file_path().unwrap(), env::current_dir().unwrap().join("docs/read me.txt"));
+
+        assert_eq!(parse(""), Err(url::ParseError::RelativeUrlWithoutBase));
+        assert!(parse("https://exa mple.com").is_err());
+    }
+
+    #[test]
+    fn parses_paths_not_in_unicode() {
+        let path = OsStr::from_bytes(b"/tmp/caf\xe9.txt");
+        assert_eq!(parse_os(path).unwrap().to_file_path().unwrap(), Path::new(path));
+        let relative = parse_os(OsStr::from_bytes(b"\xff")).unwrap();
+        assert_eq!(relative.to_file_path().unwrap(), env::current_dir().unwrap().join(OsStr::from_bytes(b"\xff")));
+
+        assert_eq!(parse_os(OsStr::from_bytes(b"https://\xff")), Err(InputError::NotUnicode));
+        assert_eq!(parse_os(OsStr::new("https://example.com")).unwrap().host_str(), Some("example.com"));
+        assert!(has_scheme(b"x-scheme+1.0:"));
+        assert!(!has_scheme(b"./a:b"));
+    }
+
+    #[test]
+    fn splits_raw_streams() {
+        let stream = b"/tmp/\xff\0/tmp/a\nb\0\0";
+        let entries: Vec<_> = split(stream, Separator::Nul).iter().map(|entry| entry.as_bytes()).collect();
+        assert_eq!(entries, [&b"/tmp/\xff"[..], b"/tmp/a\nb"]);
+        assert!(split(b" \n\t\n", Separator::Newline).is_empty());
+    }
+
+    #[test]
+    fn extracts_urls_from_text() {
+        let text = "Links: <https://example.com/a?b=c>, \"ftp://files.example.com/x\";\n\
+                    mail mailto:user@example.com! See [docs](https://example.com/docs).\n\
+                    Bare http:// and example.com are ignored; WWW.Example.COM/x?";
+        assert_eq!(
+            extract(text),
+            [
+                "https://example.com/a?b=c",
+                "ftp://files.example.com/x",
+                "mailto:user@example.com",
+                "https://example.com/docs",
+                "http://WWW.Example.COM/x",
+            ]
+        );
+        assert!(extract("nothing to see here").is_empty());
+    }
+
+    #[test]
+    fn trims_punctuation() {
+        assert_eq!(trim_punctuation("https://a/b)."), "https://a/b");
+        assert_eq!(trim_punctuation("https://a/(b)"), "https://a/(b)");
+        assert_eq!(trim_punctuation("https://a/b?!'"), "https://a/b");
+        assert_eq!(trim_punctuation("https://a/[b]]"), "https://a/[b]");
+    }
+
+    #[test]
+    fn reads_clipboard_with_command() {
+        let argv = |words: &[&str]| words.iter().map(|&word| word.to_owned()).collect::<Vec<_>>();
+        assert_eq!(clipboard(&argv(&["echo", "https://example.com"])).unwrap(), "https://example.com\n");
+        match clipboard(&argv(&["false"])) {
+            Err(ClipboardError::Failed(status)) => assert!(!status.success()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match clipboard(&argv(&["asdfghjkl"])) {
+            Err(ClipboardError::Launch(::Error::NotFound(_))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Registration as the default handler of URL schemes for the current user.
+//!
+//! Installation writes a desktop entry to `$XDG_DATA_HOME/applications`
+//! and makes it the default application for the `x-scheme-handler/<scheme>` MIME types
+//! in `$XDG_CONFIG_HOME/mimeapps.list`. The previous defaults stay listed after it,
+//! so uninstallation restores them by removing the entry from the lists.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::{error, fmt, fs, io};
+
+use mimeapps;
+use xdg;
+
+/// ID of the installed desktop entry.
+pub const DESKTOP_ID: &str = "urldispatch.desktop";
+
+/// Schemes handled when none are specified.
+pub const SCHEMES: &[&str] = &["http", "https"];
+
+/// Reasons for failed (un)installation.
+#[derive(Debug)]
+pub enum InstallError {
+    /// The XDG directories could not be determined.
+    NoHome,
+    /// Not a valid URL scheme.
+    InvalidScheme(String),
+    /// A file could not be read or written.
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InstallError::NoHome => write!(f, "cannot determine home directory"),
+            InstallError::InvalidScheme(ref scheme) => write!(f, "invalid scheme: {}", scheme),
+            InstallError::Io(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+        }
+    }
+}
+
+impl error::Error for InstallError {}
+
+/// Quote an argument of the `Exec` key, if needed.
+fn quote(arg: &str) -> String {
+    const RESERVED: &str = " \t\n\"'\\><~|&;$*?#()`";
+    if !arg.is_empty() && !arg.chars().any(|c| RESERVED.contains(c)) {
+        return arg.to_owned();
+    }
+
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        if "\"`$\\".contains(c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Escape a string value of a desktop entry.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Check and normalize the URL scheme.
+fn scheme(scheme: &str) -> Result<String, InstallError> {
+    let mut chars = scheme.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    if valid {
+        Ok(scheme.to_ascii_lowercase())
+    } else {
+        Err(InstallError::InvalidScheme(scheme.to_owned()))
+    }
+}
+
+/// Desktop entry running the program for the URL schemes.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::desktop::DesktopEntry;
+/// use urldispatch::install;
+/// let source = install::desktop_entry("/opt/url dispatch".as_ref(), &["https".into()]);
+/// assert!(source.contains("MimeType=x-scheme-handler/https;\n"));
+/// let entry = DesktopEntry::parse(install::DESKTOP_ID, "urldispatch.desktop", &source).unwrap();
+/// assert_eq!(entry.exec(), ["/opt/url dispatch", "--", "%U"]);
+/// ```
+pub fn desktop_entry(program: &Path, schemes: &[String]) -> String {
+    let mime_types: String = schemes
+        .iter()
+        .map(|scheme| format!("x-scheme-handler/{};", scheme))
+        .collect();
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name=urldispatch\n\
+         Comment=Open URLs with the configured launchers\n\
+         Exec={} -- %U\n\
+         NoDisplay=true\n\
+         MimeType={}\n",
+        escape(&quote(&program.to_string_lossy())),
+        mime_types
+    )
+}
+
+/// Read the file; missing file is empty.
+fn read(path: &Path) -> Result<String, InstallError> {
+    match fs::read_to_string(path) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        result => result.map_err(|err| InstallError::Io(path.into(), err)),
+    }
+}
+
+/// Replace the file contents atomically, creating the directory if needed.
+fn replace(path: &Path, contents: &str) -> Result<(), InstallError> {
+    let io = |err| InstallError::Io(path.into(), err);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(io)?;
+    }
+    let mut temporary = OsString::from(path);
+    temporary.push(".tmp");
+    fs::write(&temporary, contents).map_err(io)?;
+    fs::rename(&temporary, path).map_err(io)
+}
+
+/// Locations of the installed files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Installation {
+    /// The desktop entry.
+    pub desktop: PathBuf,
+    /// The edited `mimeapps.list`.
+    pub mimeapps: PathBuf,
+}
+
+impl Installation {
+    /// Locations for the current user.
+    pub fn locate() -> Result<Installation, InstallError> {
+        let data = xdg::data_home().ok_or(InstallError::NoHome)?;
+        let config = xdg::config_home().ok_or(InstallError::NoHome)?;
+        Ok(Installation {
+            desktop: data.join("applications").join(DESKTOP_ID),
+            mimeapps: config.join("mimeapps.list"),
+        })
+    }
+
+    /// Install the program as the default handler of the URL schemes;
+    /// returns the MIME types of the schemes.
+    pub fn install<S: AsRef<str>>(&self, program: &Path, schemes: &[S]) -> Result<Vec<String>, InstallError> {
+        let schemes = schemes
+            .iter()
+            .map(|name| scheme(name.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mime_types: Vec<String> = schemes
+            .iter()
+            .map(|scheme| format!("x-scheme-handler/{}", scheme))
+            .collect();
+
+        replace(&self.desktop, &desktop_entry(program, &schemes))?;
+        let mimeapps = read(&self.mimeapps)?;
+        replace(&self.mimeapps, &mimeapps::set_default(&mimeapps, DESKTOP_ID, &mime_types))?;
+        Ok(mime_types)
+    }
+
+    /// Remove the desktop entry and restore the previous default handlers.
+    pub fn uninstall(&self) -> Result<(), InstallError> {
+        let mimeapps = read(&self.mimeapps)?;
+        if !mimeapps.is_empty() {
+            replace(&self.mimeapps, &mimeapps::unset_default(&mimeapps, DESKTOP_ID))?;
+        }
+        match fs::remove_file(&self.desktop) {
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result.map_err(|err| InstallError::Io(self.desktop.clone(), err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use desktop::DesktopEntry;
+    use std::{env, process};
+
+    #[test]
+    fn quotes_exec_arguments() {
+        assert_eq!(quote("/usr/bin/urldispatch"), "/usr/bin/urldispatch");
+        assert_eq!(quote("/opt/a b"), "\"/opt/a b\"");
+        assert_eq!(quote("/opt/$a\\b"), "\"/opt/\\$a\\\\b\"");
+
+        let program = "/opt/we\"ird $path\\with\nall";
+        let source = desktop_entry(program.as_ref(), &["http".into()]);
+        let entry = DesktopEntry::parse(DESKTOP_ID, "urldispatch.desktop", &source).unwrap();
+        assert_eq!(entry.exec(), [program, "--", "%U"]);
+    }
+
+    #[test]
+    fn installs_and_restores() {
+        let root = env::temp_dir().join(format!("urldispatch-install-{}", process::id()));
+        let installation = Installation {
+            desktop: root.join("data/applications").join(DESKTOP_ID),
+            mimeapps: root.join("config/mimeapps.list"),
+        };
+        let previous = "[Default Applications]\nx-scheme-handler/http=firefox.desktop;\n";
+        replace(&installation.mimeapps, previous).unwrap();
+
+        match installation.install(Path::new("/bin/urldispatch"), &["http", "1nvalid"]) {
+            Err(InstallError::InvalidScheme(ref scheme)) => assert_eq!(scheme, "1nvalid"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let mime_types = installation.install(Path::new("/bin/urldispatch"), &["HTTP", "https"]).unwrap();
+        assert_eq!(mime_types, ["x-scheme-handler/http", "x-scheme-handler/https"]);
+        assert!(installation.desktop.is_file());
+        assert_eq!(
+            read(&installation.mimeapps).unwrap(),
+            "[Default Applications]\nx-scheme-handler/http=urldispatch.desktop;firefox.desktop;\n\
+             x-scheme-handler/https=urldispatch.desktop;\n"
+        );
+
+        installation.uninstall().unwrap();
+        assert!(!installation.desktop.exists());
+        assert_eq!(read(&installation.mimeapps).unwrap(), previous);
+        installation.uninstall().unwrap();
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+//! Running instances: reusing an already started program instead of starting another one.
+//!
+//! Instances are found by scanning `/proc` for processes of the current user
+//! with matching executable or command line.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use nix::unistd::Pid;
+
+use template::Template;
+
+/// Command handing the URL over to a running instance of the launched program,
+/// such as `firefox --new-tab %u` or `emacsclient -n %f`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    process: String,
+    template: Template,
+}
+
+impl Remote {
+    /// Use the template while a process of the program is running.
+    ///
+    /// The program is either a name, compared with the file names of the executable
+    /// and the first command line argument, or an absolute path, compared with them whole.
+    pub fn new<S: Into<String>>(process: S, template: Template) -> Remote {
+        Remote {
+            process: process.into(),
+            template,
+        }
+    }
+
+    /// Program of the running instance.
+    pub fn process(&self) -> &str {
+        &self.process
+    }
+
+    /// Command line template for the running instance.
+    pub fn template(&self) -> &Template {
+        &self.template
+    }
+
+    /// Find the running instance; returns its PID.
+    pub fn running(&self) -> Option<Pid> {
+        find(&self.process)
+    }
+}
+
+/// Check if the path refers to the program.
+fn is_program(path: &Path, program: &str) -> bool {
+    if program.contains('/') {
+        path == Path::new(program)
+    } else {
+        path.file_name() == Some(OsStr::new(program))
+    }
+}
+
+/// Check if the process, given by its `/proc` directory, runs the program.
+fn runs(process: &Path, program: &str) -> bool {
+    // Replaced executables are reported with a suffix
+    let executable = fs::read_link(process.join("exe")).ok().map(|exe| {
+        let bytes = exe.as_os_str().as_bytes();
+        let bytes = bytes.strip_suffix(b" (deleted)").unwrap_or(bytes);
+        PathBuf::from(OsStr::from_bytes(bytes))
+    });
+    // Kernel threads and zombies have empty command line
+    let argv0 = fs::read(process.join("cmdline")).ok().and_then(|cmdline| {
+        let first = cmdline.split(|&byte| byte == 0).next()?;
+        Some(PathBuf::from(OsStr::from_bytes(first))).filter(|first| !first.as_os_str().is_empty())
+    });
+
+    executable.iter().chain(&argv0).any(|path| is_program(path, program))
+}
+
+/// Find a process of the current user, other than the caller, running the program;
+/// returns its PID.
+///
+/// See [`Remote::new`](struct.Remote.html#method.new) for how the program is matched.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::instance;
+/// if let Some(pid) = instance::find("emacs") {
+///     println!("emacs is running as {}", pid);
+/// }
+/// ```
+pub fn find(program: &str) -> Option<Pid> {
+    let owner = fs::metadata("/proc/self").ok()?.uid();
+    let caller = process::id();
+
+    fs::read_dir("/proc")
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            Some((pid, entry.path()))
+        })
+        .filter(|&(pid, ref path)| {
+            // Processes may exit while being examined
+            pid != caller && fs::metadata(path).is_ok_and(|metadata| metadata.uid() == owner)
+        })
+        .find(|entry| runs(&entry.1, program))
+        .map(|(pid, _)| Pid::from_raw(pid as i32))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nix::sys::signal::{kill, Signal};
+    use std::process::Command;
+
+    #[test]
+    fn finds_running_program() {
+        let mut sleeper = Command::new("sleep").arg("5").spawn().unwrap();
+        let pid = Pid::from_raw(sleeper.id() as i32);
+
+        let process = Path::new("/proc").join(pid.to_string());
+        assert!(runs(&process, "sleep"));
+        assert!(!runs(&process, "/sleep"));
+        assert!(find("sleep").is_some());
+        assert!(find("asdfghjkl").is_none());
+
+        kill(pid, Signal::SIGTERM).unwrap();
+        sleeper.wait().unwrap();
+    }
+
+    #[test]
+    fn matches_names_and_paths() {
+        assert!(is_program(Path::new("/usr/bin/emacs"), "emacs"));
+        assert!(is_program(Path::new("emacs"), "emacs"));
+        assert!(is_program(Path::new("/usr/bin/emacs"), "/usr/bin/emacs"));
+        assert!(!is_program(Path::new("/usr/local/bin/emacs"), "/usr/bin/emacs"));
+        assert!(!is_program(Path::new("/usr/bin/emacsclient"), "emacs"));
+    }
+}
@@ -0,0 +1,432 @@
+//! Launchers: commands for opening URLs, along with the way they are started.
+
+use std::collections::BTreeMap;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::{env, error, fmt, process, slice};
+
+use nix::sys::stat::{self, Mode};
+use nix::unistd::Pid;
+use url::Url;
+
+use browser::Browser;
+use desktop::{DesktopEntry, DesktopError};
+use instance::Remote;
+use sh::{self, Backend, Detach};
+use template::{Template, TemplateError};
+
+/// Reasons for a launcher command that could not be prepared or started.
+#[derive(Debug)]
+pub enum LauncherError {
+    /// The command line could not be prepared.
+    Template(TemplateError),
+    /// The desktop entry is unusable.
+    Desktop(DesktopError),
+    /// The command could not be started.
+    Launch(::Error),
+    /// The launcher options cannot be honoured by its backend.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for LauncherError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LauncherError::Template(ref err) => err.fmt(f),
+            LauncherError::Desktop(ref err) => err.fmt(f),
+            LauncherError::Launch(ref err) => err.fmt(f),
+            LauncherError::Unsupported(message) => f.write_str(message),
+        }
+    }
+}
+
+impl error::Error for LauncherError {}
+
+impl From<TemplateError> for LauncherError {
+    fn from(err: TemplateError) -> LauncherError {
+        LauncherError::Template(err)
+    }
+}
+impl From<DesktopError> for LauncherError {
+    /// Missing entry is treated as missing program.
+    fn from(err: DesktopError) -> LauncherError {
+        match err {
+            DesktopError::NotFound(id) => LauncherError::Launch(::Error::NotFound(id.into())),
+            err => LauncherError::Desktop(err),
+        }
+    }
+}
+impl From<::Error> for LauncherError {
+    fn from(err: ::Error) -> LauncherError {
+        LauncherError::Launch(err)
+    }
+}
+
+/// Source of the launched command line.
+#[derive(Debug, Clone)]
+pub enum Program {
+    /// Command line template.
+    Template(Template),
+    /// ID of a desktop entry, looked up whenever the launcher is used.
+    Desktop(String),
+    /// Browser profile or container.
+    Browser(Browser),
+}
+
+/// Changes of the environment of the launched commands.
+///
+/// The default keeps the caller's environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Environment {
+    /// Start with an empty environment instead of the caller's one.
+    pub clear: bool,
+    /// Variables to remove.
+    pub unset: Vec<String>,
+    /// Variables to set, after the removals.
+    pub set: BTreeMap<String, String>,
+}
+
+impl Environment {
+    /// Apply the changes to the command.
+    pub fn apply(&self, command: &mut process::Command) {
+        if self.clear {
+            // Explicit removals, unlike env_clear, are honoured by the spawn backend
+            for (name, _) in env::vars_os() {
+                command.env_remove(name);
+            }
+        }
+        for name in &self.unset {
+            command.env_remove(name);
+        }
+        command.envs(&self.set);
+    }
+}
+
+/// Named command for opening URLs.
+#[derive(Debug, Clone)]
+pub struct Launcher {
+    name: String,
+    program: Program,
+    detach: Detach,
+    backend: Backend,
+    environment: Environment,
+    cwd: Option<PathBuf>,
+    umask: Option<u32>,
+    remote: Option<Remote>,
+}
+
+impl Launcher {
+    /// Create a launcher that is not detached from the caller,
+    /// using the default backend.
+    pub fn new<S: Into<String>>(name: S, template: Template) -> Launcher {
+        Launcher::with_program(name, Program::Template(template))
+    }
+
+    /// Create a launcher running the desktop entry with the ID,
+    /// such as `firefox.desktop`.
+    pub fn desktop<S: Into<String>, I: Into<String>>(name: S, id: I) -> Launcher {
+        Launcher::with_program(name, Program::Desktop(id.into()))
+    }
+
+    /// Create a launcher opening URLs in the browser profile.
+    pub fn browser<S: Into<String>>(name: S, browser: Browser) -> Launcher {
+        Launcher::with_program(name, Program::Browser(browser))
+    }
+
+    fn with_program<S: Into<String>>(name: S, program: Program) -> Launcher {
+        Launcher {
+            name: name.into(),
+            program,
+            detach: Detach::default(),
+            backend: Backend::default(),
+            environment: Environment::default(),
+            cwd: None,
+            umask: None,
+            remote: None,
+        }
+    }
+
+    /// Specify how to detach the launched commands.
+    pub fn with_detach(mut self, detach: Detach) -> Launcher {
+        self.detach = detach;
+        self
+    }
+
+    /// Specify how to start the launched commands.
+    pub fn with_backend(mut self, backend: Backend) -> Launcher {
+        self.backend = backend;
+        self
+    }
+
+    /// Change the environment of the launched commands.
+    pub fn with_environment(mut self, environment: Environment) -> Launcher {
+        self.environment = environment;
+        self
+    }
+
+    /// Start the commands in the directory; it takes precedence over `Detach::chdir_root`.
+    pub fn with_cwd<P: Into<PathBuf>>(mut self, cwd: P) -> Launcher {
+        self.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Set the file mode creation mask of the commands.
+    ///
+    /// The mask is applied between `fork` and `exec`, which the spawn backend cannot do;
+    /// dispatching such a launcher with it fails.
+    pub fn with_umask(mut self, umask: u32) -> Launcher {
+        self.umask = Some(umask);
+        self
+    }
+
+    /// Hand the URLs over to a running instance of the program, if there is one,
+    /// instead of starting a new one.
+    pub fn with_remote(mut self, remote: Remote) -> Launcher {
+        self.remote = Some(remote);
+        self
+    }
+
+    /// Name of the launcher.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Source of the command line.
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// How to detach the launched commands.
+    pub fn detach(&self) -> &Detach {
+        &self.detach
+    }
+
+    /// How to start the launched commands.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Changes of the environment of the launched commands.
+    pub fn environment(&self) -> &Environment {
+        &self.environment
+    }
+
+    /// Working directory of the launched commands, if changed.
+    pub fn cwd(&self) -> Option<&Path> {
+        self.cwd.as_deref()
+    }
+
+    /// File mode creation mask of the launched commands, if changed.
+    pub fn umask(&self) -> Option<u32> {
+        self.umask
+    }
+
+    /// Command for a running instance of the program, if set.
+    pub fn remote(&self) -> Option<&Remote> {
+        self.remote.as_ref()
+    }
+
+    /// Template of the remote command, if an instance of the program is running.
+    ///
+    /// Finding the instance scans all processes, so it is done once per dispatch
+    /// and the result passed to [`argv`](#method.argv) and [`argv_all`](#method.argv_all).
+    pub fn running_remote(&self) -> Option<&Template> {
+        self.remote
+            .as_ref()
+            .filter(|remote| remote.running().is_some())
+            .map(Remote::template)
+    }
+
+    /// Produce an argument vector for the URL; with the remote command of a running instance,
+    /// as found by [`running_remote`](#method.running_remote), if there is one.
+    ///
+    /// Missing desktop entry is reported as missing program,
+    /// so that the next fallback launcher is tried.
+    pub fn argv(&self, url: &Url, remote: Option<&Template>) -> Result<Vec<String>, LauncherError> {
+        if let Some(template) = remote {
+            return Ok(template.argv(url)?);
+        }
+        match self.program {
+            Program::Template(ref template) => Ok(template.argv(url)?),
+            Program::Desktop(ref id) => Ok(DesktopEntry::find(id)?.argv(slice::from_ref(url))?),
+            Program::Browser(ref browser) => Ok(browser.argv(url)),
+        }
+    }
+
+    /// Produce a single argument vector for all the URLs, if the command
+    /// opens multiple URLs at once; `None` if it takes only one.
+    ///
+    /// Templates and desktop entries open multiple URLs with `%U` or `%F`,
+    /// browsers always do.
+    pub fn argv_all(&self, urls: &[Url], remote: Option<&Template>) -> Result<Option<Vec<String>>, LauncherError> {
+        if let Some(template) = remote {
+            if !template.accepts_multiple() {
+                return Ok(None);
+            }
+            return Ok(Some(template.argv_all(urls)?));
+        }
+        match self.program {
+            Program::Template(ref template) if template.accepts_multiple() => Ok(Some(template.argv_all(urls)?)),
+            Program::Template(_) => Ok(None),
+            Program::Desktop(ref id) => {
+                let entry = DesktopEntry::find(id)?;
+                if !entry.accepts_multiple() {
+                    return Ok(None);
+                }
+                Ok(Some(entry.argv(urls)?))
+            }
+            Program::Browser(ref browser) => Ok(Some(browser.argv_all(urls))),
+        }
+    }
+
+    /// Prepare a command for the argument vector, in the launcher's environment.
+    pub(crate) fn prepare(&self, argv: &[String]) -> Option<process::Command> {
+        let mut command = sh::command(argv)?;
+        self.environment.apply(&mut command);
+        if let Some(ref cwd) = self.cwd {
+            command.current_dir(cwd);
+        }
+        if let Some(umask) = self.umask {
+            let mask = Mode::from_bits_truncate(umask as _);
+            // umask is async-signal-safe
+            unsafe {
+                command.pre_exec(move || {
+                    stat::umask(mask);
+                    Ok(())
+                })
+            };
+        }
+        Some(command)
+    }
+
+    /// Prepare a command for [`sh::dispatch_using`](../sh/fn.dispatch_using.html).
+    pub fn command(&self, url: &Url) -> Result<process::Command, LauncherError> {
+        self.prepare(&self.argv(url, self.running_remote())?).ok_or_else(|| TemplateError::Empty.into())
+    }
+
+    /// Start the command for the URL in the background; returns its PID.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate urldispatch;
+    /// use urldispatch::launcher::{Environment, Launcher};
+    /// let mut environment = Environment::default();
+    /// environment.set.insert("MOZ_ENABLE_WAYLAND".into(), "1".into());
+    /// environment.unset.push("LD_PRELOAD".into());
+    /// let launcher = Launcher::new("true", "true %u".parse().unwrap())
+    ///     .with_environment(environment)
+    ///     .with_cwd("/")
+    ///     .with_umask(0o077);
+    /// let url = url::Url::parse("https://example.com").unwrap();
+    /// launcher.dispatch(&url).expect("Failed to dispatch!");
+    /// ```
+    pub fn dispatch(&self, url: &Url) -> Result<Pid, LauncherError> {
+        self.start(self.command(url)?)
+    }
+
+    /// Start the prepared command, as the launcher specifies.
+    pub(crate) fn start(&self, command: process::Command) -> Result<Pid, LauncherError> {
+        if self.umask.is_some() && self.backend == Backend::Spawn {
+            return Err(LauncherError::Unsupported("umask is not supported by the spawn backend"));
+        }
+        Ok(sh::dispatch_using(command, &self.detach, self.backend)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use browser::Family;
+    use sh::Output;
+    use std::thread::sleep;
+    use std::time::Duration;
+    use std::{fs, process};
+
+    #[test]
+    fn launcher_prepares_environment() {
+        let url = Url::parse("https://example.com/").unwrap();
+        for &(backend, umask) in &[(Backend::Fork, Some(0o077)), (Backend::Spawn, None)] {
+            let log = env::temp_dir().join(format!("urldispatch-launcher-{}-{:?}.log", process::id(), backend));
+            let _ = fs::remove_file(&log);
+
+            let mut environment = Environment {
+                clear: true,
+                ..Environment::default()
+            };
+            environment.set.insert("KEEP".into(), "kept".into());
+            let template = r#"/bin/sh -c 'echo "${KEEP-unset}:${HOME-unset}:$0"; pwd; umask' %u"#;
+            let mut launcher = Launcher::new("test", template.parse().unwrap())
+                .with_environment(environment)
+                .with_cwd("/tmp")
+                .with_backend(backend)
+                .with_detach(Detach {
+                    output: Output::Log(log.clone()),
+                    ..Detach::default()
+                });
+            if let Some(umask) = umask {
+                launcher = launcher.with_umask(umask);
+            }
+
+            launcher.dispatch(&url).unwrap();
+            sleep(Duration::from_millis(500));
+
+            let output = fs::read_to_string(&log).unwrap();
+            fs::remove_file(&log).unwrap();
+            let lines: Vec<_> = output.lines().collect();
+            assert_eq!(lines[..2], ["kept:unset:https://example.com/", "/tmp"], "{:?}", backend);
+            if umask.is_some() {
+                assert_eq!(lines[2], "0077");
+            }
+        }
+
+        // The spawn backend cannot set umask
+        let launcher = Launcher::new("test", "true %u".parse().unwrap())
+            .with_backend(Backend::Spawn)
+            .with_umask(0o077);
+        match launcher.dispatch(&url) {
+            Err(LauncherError::Unsupported(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn launcher_uses_running_instance() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let launcher = |process: &str| {
+            Launcher::new("test", "asdfghjkl %u".parse().unwrap())
+                .with_remote(Remote::new(process, "true --remote %u".parse().unwrap()))
+        };
+
+        let mut sleeper = process::Command::new("sleep").arg("5").spawn().unwrap();
+        let sleep = launcher("sleep");
+        let remote = sleep.running_remote();
+        assert_eq!(sleep.argv(&url, remote).unwrap(), ["true", "--remote", "https://example.com/"]);
+        sleeper.kill().unwrap();
+        sleeper.wait().unwrap();
+
+        let missing = launcher("qwertyuiop");
+        assert!(missing.running_remote().is_none());
+        assert_eq!(missing.argv(&url, None).unwrap(), ["asdfghjkl", "https://example.com/"]);
+    }
+
+    #[test]
+    fn launcher_batches_urls() {
+        let urls = [
+            Url::parse("https://example.com/").unwrap(),
+            Url::parse("https://example.org/").unwrap(),
+        ];
+        let launcher = |template: &str| Launcher::new("test", template.parse().unwrap());
+
+        assert_eq!(
+            launcher("mpv -- %U").argv_all(&urls, None).unwrap(),
+            Some(vec!["mpv".into(), "--".into(), "https://example.com/".into(), "https://example.org/".into()])
+        );
+        assert_eq!(launcher("mpv -- %u").argv_all(&urls, None).unwrap(), None);
+        let browser = Launcher::browser("test", Browser::new(Family::Chromium));
+        let argv = browser.argv_all(&urls[1..], None).unwrap();
+        assert_eq!(argv, Some(vec!["chromium".into(), "https://example.org/".into()]));
+    }
+}
@@ -1,6 +1,28 @@
 extern crate nix;
+extern crate regex;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
+extern crate url;
 
+mod error;
+
+pub mod browser;
+pub mod config;
+pub mod desktop;
+pub mod input;
+pub mod instance;
+pub mod install;
+pub mod launcher;
+pub mod mime;
+pub mod mimeapps;
+pub mod rewrite;
+pub mod rules;
 pub mod sh;
+pub mod template;
+pub mod xdg;
+
+pub use error::Error;
 
 #[cfg(test)]
 mod tests {
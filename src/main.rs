@@ -0,0 +1,513 @@
+//! Command-line interface: open URLs with the configured launchers.
+
+#[cfg(test)]
+extern crate nix;
+extern crate url;
+extern crate urldispatch;
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::{env, process, str};
+
+use url::Url;
+
+use urldispatch::config::Config;
+use urldispatch::input::{self, Separator};
+use urldispatch::install::{self, Installation};
+use urldispatch::rules::{Batch, DispatchError, Dispatcher, Rule};
+use urldispatch::{sh, Error};
+
+const USAGE: &str = "\
+Usage: urldispatch [OPTIONS] [URL|PATH...]
+       urldispatch install [SCHEME...]
+       urldispatch uninstall
+
+Open each URL with a launcher selected by the configured rules.
+Paths are opened as file:// URLs. URLs selecting the same rule are opened
+by a single command if the launcher accepts multiple URLs.
+URLs can also be read from standard input and the clipboard, one per line,
+or found in free text.
+
+Commands:
+  install            make urldispatch the default handler of the URL schemes
+                     (default: http https) for the current user
+  uninstall          restore the default handlers from before installation
+
+Options:
+  -c, --config PATH  read configuration from PATH
+                     (default: $XDG_CONFIG_HOME/urldispatch/config.toml)
+  -n, --dry-run      print the commands instead of running them
+  -r, --rule NAME    use rule NAME regardless of its conditions
+  -e, --explain      describe how the rules were evaluated
+  -i, --stdin        also read URLs from standard input
+  -p, --clipboard    also read URLs from the clipboard, printed by
+                     the configured command (default: wl-paste or xclip)
+  -0, --null         URLs read from standard input and the clipboard alike
+                     are separated by NUL instead of newline
+  -x, --extract      find URLs in the read text instead of taking whole lines
+  -h, --help         print this help and exit
+
+Exit status:
+  0       all URLs were dispatched
+  1-63    the launcher could not be started; the status is the errno value
+          (of the last fallback, if all were missing)
+  128+N   the launcher helper was killed by signal N
+  64      invalid command line
+  65      invalid URL, no URL in the input, rewrite loop, no matching rule,
+          unusable launcher command or desktop entry, or unknown launcher chosen
+  74      (un)installation failed, or input could not be read
+  78      invalid or missing configuration
+";
+
+/// Exit codes, as defined in `sysexits.h`.
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_IOERR: i32 = 74;
+const EX_CONFIG: i32 = 78;
+/// Fallback for launch failures without usable errno.
+const EX_FAILURE: i32 = 1;
+
+/// Parsed command line.
+#[derive(Debug, Default)]
+struct Options {
+    config: Option<PathBuf>,
+    dry_run: bool,
+    rule: Option<String>,
+    explain: bool,
+    stdin: bool,
+    clipboard: bool,
+    null: bool,
+    extract: bool,
+    urls: Vec<String>,
+}
+
+/// Reason to stop processing, with message for the user and exit code.
+#[derive(Debug)]
+struct Failure {
+    message: String,
+    code: i32,
+}
+
+impl Failure {
+    fn new<M: ToString>(message: M, code: i32) -> Failure {
+        Failure {
+            message: message.to_string(),
+            code,
+        }
+    }
+}
+
+/// Map the launch failure to an exit code.
+fn launch_status(err: &Error) -> i32 {
+    match (err, err.raw_os_error()) {
+        (&Error::Signaled(signal), _) => 128 + signal as i32,
+        (_, Some(errno)) if (1..EX_USAGE).contains(&errno) => errno,
+        _ => EX_FAILURE,
+    }
+}
+
+/// Map the dispatch failure to an exit code.
+fn dispatch_failure(rule: &Rule, err: &DispatchError) -> Failure {
+    let code = match *err {
+        DispatchError::Launch(ref err) => launch_status(err),
+        // Status of the last launcher, as the shell does for the last command
+        DispatchError::Exhausted(ref attempts) => {
+            attempts.last().map_or(EX_FAILURE, |attempt| launch_status(&attempt.error))
+        }
+        DispatchError::NoMatch(_) | DispatchError::Template(_) | DispatchError::Desktop(_) => EX_DATAERR,
+        DispatchError::UnknownChoice(_) | DispatchError::Rewrite(_) => EX_DATAERR,
+        DispatchError::Unsupported(_) => EX_CONFIG,
+        DispatchError::Cancelled => EX_FAILURE,
+    };
+    Failure::new(format!("rule {}: {}", rule.name(), err), code)
+}
+
+/// Parse the command line arguments; `None` means help was requested.
+fn parse_args<I: IntoIterator<Item = OsString>>(args: I) -> Result<Option<Options>, Failure> {
+    let usage = |message: String| Failure::new(message, EX_USAGE);
+    let mut options = Options::default();
+    let mut args = args.into_iter().map(|arg| {
+        arg.into_string()
+            .map_err(|arg| usage(format!("invalid argument: {}", arg.to_string_lossy())))
+    });
+
+    while let Some(arg) = args.next() {
+        let arg = arg?;
+        let (flag, inline) = match arg.find('=') {
+            Some(eq) if arg.starts_with("--") => (&arg[..eq], Some(arg[eq + 1..].to_owned())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || match inline.clone() {
+            Some(value) => Ok(value),
+            None => args
+                .next()
+                .unwrap_or_else(|| Err(usage(format!("option {} requires a value", flag)))),
+        };
+        let switch = || match inline {
+            None => Ok(true),
+            Some(_) => Err(usage(format!("option {} takes no value", flag))),
+        };
+
+        match flag {
+            "-h" | "--help" => return Ok(None),
+            "-c" | "--config" => options.config = Some(value()?.into()),
+            "-r" | "--rule" => options.rule = Some(value()?),
+            "-n" | "--dry-run" => options.dry_run = switch()?,
+            "-e" | "--explain" => options.explain = switch()?,
+            "-i" | "--stdin" => options.stdin = switch()?,
+            "-p" | "--clipboard" => options.clipboard = switch()?,
+            "-0" | "--null" => options.null = switch()?,
+            "-x" | "--extract" => options.extract = switch()?,
+            "--" => {
+                for url in args {
+                    options.urls.push(url?);
+                }
+                break;
+            }
+            flag if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(usage(format!("unknown option: {}", flag)))
+            }
+            _ => options.urls.push(arg.clone()),
+        }
+    }
+
+    if options.urls.is_empty() && !options.stdin && !options.clipboard {
+        return Err(usage("no URL given".into()));
+    }
+    Ok(Some(options))
+}
+
+/// Select the rule for the URL, describing the process if requested.
+fn select<'d>(dispatcher: &'d Dispatcher, options: &Options, url: &Url) -> Result<Cow<'d, Rule>, Failure> {
+    let describe = |rule: &Rule, verdict: &str| {
+        if options.explain {
+            println!("  rule {}: {}", rule.name(), verdict);
+            for check in rule.explain(url) {
+                println!("    {}", check);
+            }
+        }
+    };
+    if let Some(ref name) = options.rule {
+        let rule = dispatcher
+            .rule(name)
+            .ok_or_else(|| Failure::new(format!("unknown rule: {}", name), EX_USAGE))?;
+        describe(rule, "forced");
+        return Ok(Cow::Borrowed(rule));
+    }
+
+    for rule in dispatcher.rules() {
+        if rule.matches(url) {
+            describe(rule, "selected");
+            return Ok(Cow::Borrowed(rule));
+        }
+        describe(rule, "no match");
+    }
+    if let Some(rule) = dispatcher.default_rule(url) {
+        describe(&rule, "default handler");
+        return Ok(Cow::Owned(rule));
+    }
+    Err(Failure::new(format!("no rule matches {}", url), EX_DATAERR))
+}
+
+/// Interpret, rewrite and select the rule for a single URL.
+fn resolve<'d>(
+    dispatcher: &'d Dispatcher,
+    options: &Options,
+    input: &OsStr,
+) -> Result<(Url, Cow<'d, Rule>), Failure> {
+    let url = input::parse_os(input)
+        .map_err(|err| Failure::new(format!("{}: {}", input.to_string_lossy(), err), EX_DATAERR))?;
+    if options.explain {
+        println!("{}:", url);
+    }
+    let rewritten = dispatcher
+        .rewriter()
+        .rewrite(&url)
+        .map_err(|err| Failure::new(format!("{}: {}", url, err), EX_DATAERR))?;
+    if options.explain {
+        for step in &rewritten.steps {
+            println!("  {}", step);
+        }
+    }
+
+    let url = rewritten.url;
+    let rule = select(dispatcher, options, &url)?;
+    Ok((url, rule))
+}
+
+/// Print the command line, quoted for the shell.
+fn print_argv(argv: &[String]) {
+    let quoted: Vec<_> = argv.iter().map(|word| sh::quote(word)).collect();
+    println!("{}", quoted.join(" "));
+}
+
+/// Open the URLs of a batch; returns the failures, with the positions of the URLs.
+fn open(batch: &Batch, options: &Options) -> Vec<(usize, Failure)> {
+    let rule = &batch.rule;
+    let mut failures = Vec::new();
+
+    if options.dry_run {
+        let remote = rule.launcher().running_remote();
+        match rule.launcher().argv_all(&batch.urls, remote) {
+            Ok(Some(argv)) => print_argv(&argv),
+            Ok(None) | Err(_) => {
+                for (&position, url) in batch.positions.iter().zip(&batch.urls) {
+                    match rule.launcher().argv(url, remote) {
+                        Ok(argv) => print_argv(&argv),
+                        Err(err) => failures.push((position, dispatch_failure(rule, &err.into()))),
+                    }
+                }
+            }
+        }
+        return failures;
+    }
+
+    // Launchers started once for several URLs are reported once
+    let mut reported = None;
+    for (&position, outcome) in batch.positions.iter().zip(batch.dispatch()) {
+        match outcome {
+            Ok(ref dispatched) if reported != Some(dispatched.pid) => {
+                for attempt in &dispatched.failures {
+                    eprintln!("urldispatch: rule {}: {}; trying next", rule.name(), attempt);
+                }
+                reported = Some(dispatched.pid);
+            }
+            Ok(_) => {}
+            Err(err) => failures.push((position, dispatch_failure(rule, &err))),
+        }
+    }
+    failures
+}
+
+/// Collect the URLs from the arguments, standard input and clipboard, in this order.
+fn inputs(options: &Options, config: &Config) -> Result<Vec<OsString>, Failure> {
+    let mut texts = Vec::new();
+    if options.stdin {
+        let mut text = Vec::new();
+        io::stdin()
+            .read_to_end(&mut text)
+            .map_err(|err| Failure::new(format!("standard input: {}", err), EX_IOERR))?;
+        texts.push(("standard input", text));
+    }
+    if options.clipboard {
+        let argv = config.clipboard().map_or_else(input::default_clipboard, <[String]>::to_vec);
+        let text = input::clipboard(&argv).map_err(|err| Failure::new(err, EX_IOERR))?;
+        texts.push(("clipboard", text.into_bytes()));
+    }
+
+    let separator = if options.null { Separator::Nul } else { Separator::Newline };
+    let mut urls: Vec<OsString> = options.urls.iter().map(OsString::from).collect();
+    for (source, text) in &texts {
+        if options.extract {
+            let text = str::from_utf8(text)
+                .map_err(|_| Failure::new(format!("{}: not valid UTF-8", source), EX_DATAERR))?;
+            urls.extend(input::extract(text).into_iter().map(OsString::from));
+        } else {
+            urls.extend(input::split(text, separator).into_iter().map(OsStr::to_owned));
+        }
+    }
+    if urls.is_empty() {
+        return Err(Failure::new("no URL found in the input", EX_DATAERR));
+    }
+    Ok(urls)
+}
+
+/// Install as the default handler of the schemes, or uninstall.
+fn install(args: &[OsString], uninstall: bool) -> Result<i32, Failure> {
+    let schemes = args
+        .iter()
+        .map(|arg| {
+            arg.to_str()
+                .filter(|arg| !arg.starts_with('-') && !uninstall)
+                .ok_or_else(|| Failure::new(format!("unexpected argument: {}", arg.to_string_lossy()), EX_USAGE))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let schemes = if schemes.is_empty() { install::SCHEMES.to_vec() } else { schemes };
+
+    let failure = |err: install::InstallError| Failure::new(err, EX_IOERR);
+    let installation = Installation::locate().map_err(failure)?;
+    if uninstall {
+        installation.uninstall().map_err(failure)?;
+        println!("removed {}", installation.desktop.display());
+        return Ok(0);
+    }
+
+    let program = env::current_exe().map_err(|err| Failure::new(err, EX_IOERR))?;
+    let mime_types = installation
+        .install(&program, &schemes)
+        .map_err(|err| match err {
+            install::InstallError::InvalidScheme(_) => Failure::new(err, EX_USAGE),
+            err => failure(err),
+        })?;
+    println!("installed {}", installation.desktop.display());
+    println!("default for {} in {}", mime_types.join(", "), installation.mimeapps.display());
+    Ok(0)
+}
+
+/// Open all requested URLs; returns the exit code.
+fn run() -> Result<i32, Failure> {
+    let args: Vec<OsString> = env::args_os().skip(1).collect();
+    match args.first().and_then(|arg| arg.to_str()) {
+        Some("install") => return install(&args[1..], false),
+        Some("uninstall") => return install(&args[1..], true),
+        _ => {}
+    }
+
+    let options = match parse_args(args)? {
+        Some(options) => options,
+        None => {
+            print!("{}", USAGE);
+            return Ok(0);
+        }
+    };
+
+    let config = match options.config {
+        Some(ref path) => Config::load(path),
+        None => Config::load_default(),
+    };
+    let config = config.map_err(|err| Failure::new(err, EX_CONFIG))?;
+    if let Some(ref name) = options.rule {
+        if config.dispatcher().rule(name).is_none() {
+            return Err(Failure::new(format!("unknown rule: {}", name), EX_USAGE));
+        }
+    }
+
+    let inputs = inputs(&options, &config)?;
+    let mut selected = Vec::new();
+    let mut failures = Vec::new();
+    for (position, input) in inputs.iter().enumerate() {
+        match resolve(config.dispatcher(), &options, input) {
+            Ok((url, rule)) => selected.push((position, url, rule)),
+            Err(failure) => failures.push((position, failure)),
+        }
+    }
+    for batch in Batch::group(selected) {
+        failures.extend(open(&batch, &options));
+    }
+
+    // Report every failed URL, but exit with the status of the first one.
+    failures.sort_by_key(|&(position, _)| position);
+    for (_, failure) in &failures {
+        eprintln!("urldispatch: {}", failure.message);
+    }
+    Ok(failures.first().map_or(0, |(_, failure)| failure.code))
+}
+
+fn main() {
+    match run() {
+        Ok(status) => process::exit(status),
+        Err(failure) => {
+            eprintln!("urldispatch: {}", failure.message);
+            if failure.code == EX_USAGE {
+                eprintln!("Try 'urldispatch --help' for more information.");
+            }
+            process::exit(failure.code);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use nix::errno::Errno;
+    use nix::sys::signal::Signal;
+    use urldispatch::launcher::Launcher;
+    use urldispatch::rules::Attempt;
+
+    fn args(words: &[&str]) -> Vec<OsString> {
+        words.iter().map(OsString::from).collect()
+    }
+
+    fn usage_error(words: &[&str]) -> String {
+        match parse_args(args(words)) {
+            Err(Failure { message, code: EX_USAGE }) => message,
+            other => panic!("unexpected result for {:?}: {:?}", words, other),
+        }
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = parse_args(args(&["-n", "--config=/tmp/c.toml", "-r", "web", "a", "--", "-b"]))
+            .unwrap()
+            .unwrap();
+        assert!(options.dry_run && !options.explain);
+        assert_eq!(options.config, Some(PathBuf::from("/tmp/c.toml")));
+        assert_eq!(options.rule.as_deref(), Some("web"));
+        assert_eq!(options.urls, ["a", "-b"]);
+
+        let options = parse_args(args(&["-i", "--clipboard", "-0", "--extract"])).unwrap().unwrap();
+        assert!(options.stdin && options.clipboard && options.null && options.extract);
+        assert!(options.urls.is_empty());
+        assert!(parse_args(args(&["a", "--help"])).unwrap().is_none());
+
+        assert_eq!(usage_error(&[]), "no URL given");
+        assert_eq!(usage_error(&["-n"]), "no URL given");
+        assert_eq!(usage_error(&["--bogus", "a"]), "unknown option: --bogus");
+        assert_eq!(usage_error(&["a", "--rule"]), "option --rule requires a value");
+        assert_eq!(usage_error(&["--stdin=yes"]), "option --stdin takes no value");
+    }
+
+    #[test]
+    fn maps_failures_to_exit_codes() {
+        assert_eq!(launch_status(&Error::NotFound("x".into())), 2);
+        assert_eq!(launch_status(&Error::Signaled(Signal::SIGKILL)), 137);
+        assert_eq!(launch_status(&Error::Os(Errno::EOWNERDEAD)), EX_FAILURE);
+        assert_eq!(launch_status(&Error::Unknown), EX_FAILURE);
+
+        let rule = Rule::new("web", Launcher::new("web", "web %u".parse().unwrap()));
+        let code = |err: DispatchError| dispatch_failure(&rule, &err).code;
+        assert_eq!(code(DispatchError::NoMatch("x".into())), EX_DATAERR);
+        assert_eq!(code(DispatchError::Unsupported("x")), EX_CONFIG);
+        assert_eq!(code(DispatchError::Launch(Error::PermissionDenied("x".into()))), 13);
+        let attempts = vec![
+            Attempt {
+                launcher: "a".into(),
+                error: Error::PermissionDenied("a".into()),
+            },
+            Attempt {
+                launcher: "b".into(),
+                error: Error::NotFound("b".into()),
+            },
+        ];
+        assert_eq!(code(DispatchError::Exhausted(attempts)), 2);
+
+        let failure = dispatch_failure(&rule, &DispatchError::Cancelled);
+        assert_eq!((failure.message.as_str(), failure.code), ("rule web: no launcher chosen", EX_FAILURE));
+    }
+
+    #[test]
+    fn collects_inputs() {
+        let clipboard = |command: &str| -> Config {
+            format!("[clipboard]\ncommand = {:?}\n", command).parse().unwrap()
+        };
+        let options = |urls: &[&str], null: bool, extract: bool| Options {
+            clipboard: true,
+            null,
+            extract,
+            urls: urls.iter().map(|&url| url.to_owned()).collect(),
+            ..Options::default()
+        };
+
+        let lines = clipboard("printf ' https://example.com/a \\n\\n/tmp/a b\\n'");
+        let found = inputs(&options(&["x"], false, false), &lines).unwrap();
+        assert_eq!(found, ["x", "https://example.com/a", "/tmp/a b"]);
+
+        let nul = clipboard("printf 'a\\nb\\0c'");
+        assert_eq!(inputs(&options(&[], true, false), &nul).unwrap(), ["a\nb", "c"]);
+
+        let text = clipboard("echo 'See https://example.com/a, or www.example.org.'");
+        let found = inputs(&options(&[], false, true), &text).unwrap();
+        assert_eq!(found, ["https://example.com/a", "http://www.example.org"]);
+
+        match inputs(&options(&[], false, true), &clipboard("echo nothing")) {
+            Err(Failure { code: EX_DATAERR, .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match inputs(&options(&[], false, false), &clipboard("false")) {
+            Err(Failure { code: EX_IOERR, .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
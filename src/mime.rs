@@ -0,0 +1,662 @@
+//! Detection of MIME types of local files, using the shared-mime-info database.
+//!
+//! The type is determined from the file name (`globs2`) and, if that does not
+//! decide, from the contents (`magic`). Types are related by `subclasses`,
+//! so that a pattern like `text/plain` matches source code as well.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::{error, fmt};
+
+use regex::{self, Regex, RegexBuilder};
+use url::Url;
+
+use xdg;
+
+/// Pattern of MIME types, such as `application/pdf` or `image/*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimePattern {
+    media: String,
+    subtype: Option<String>,
+}
+
+/// Reasons for invalid MIME pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MimePatternError(String);
+
+impl fmt::Display for MimePatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid MIME type pattern: {}", self.0)
+    }
+}
+
+impl error::Error for MimePatternError {}
+
+impl FromStr for MimePattern {
+    type Err = MimePatternError;
+
+    /// Parse `type/subtype`, where subtype may be `*` for any.
+    fn from_str(s: &str) -> Result<MimePattern, MimePatternError> {
+        let valid = |part: &str| !part.is_empty() && !part.contains(|c: char| c.is_whitespace() || c == '/');
+        match s.split_once('/') {
+            Some((media, subtype)) if valid(media) && valid(subtype) && media != "*" => Ok(MimePattern {
+                media: media.to_lowercase(),
+                subtype: Some(subtype.to_lowercase()).filter(|subtype| subtype != "*"),
+            }),
+            _ => Err(MimePatternError(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for MimePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.media, self.subtype.as_ref().map_or("*", String::as_str))
+    }
+}
+
+impl MimePattern {
+    /// Check if the MIME type matches the pattern (case-insensitive).
+    pub fn matches(&self, mime: &str) -> bool {
+        let mime = mime.to_lowercase();
+        match mime.split_once('/') {
+            Some((media, subtype)) => {
+                media == self.media && self.subtype.as_ref().is_none_or(|expected| subtype == expected)
+            }
+            None => false,
+        }
+    }
+}
+
+/// How the glob is matched against file names.
+#[derive(Debug, Clone)]
+enum GlobKind {
+    Literal(String),
+    Suffix(String),
+    Regex(Regex),
+}
+
+/// File name pattern of a MIME type.
+#[derive(Debug, Clone)]
+struct Glob {
+    weight: u32,
+    mime: String,
+    length: usize,
+    case_sensitive: bool,
+    kind: GlobKind,
+}
+
+impl Glob {
+    fn new(weight: u32, mime: &str, pattern: &str, case_sensitive: bool) -> Result<Glob, regex::Error> {
+        let fold = |s: &str| if case_sensitive { s.to_owned() } else { s.to_lowercase() };
+        let wildcard = |s: &str| s.contains(['*', '?', '[']);
+        let kind = if !wildcard(pattern) {
+            GlobKind::Literal(fold(pattern))
+        } else if pattern.starts_with('*') && !wildcard(&pattern[1..]) {
+            GlobKind::Suffix(fold(&pattern[1..]))
+        } else {
+            GlobKind::Regex(translate(pattern, case_sensitive)?)
+        };
+        Ok(Glob {
+            weight,
+            mime: mime.to_owned(),
+            length: pattern.len(),
+            case_sensitive,
+            kind,
+        })
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        let folded;
+        let name = if self.case_sensitive {
+            name
+        } else {
+            folded = name.to_lowercase();
+            &folded
+        };
+        match self.kind {
+            GlobKind::Literal(ref literal) => name == literal,
+            GlobKind::Suffix(ref suffix) => name.ends_with(suffix.as_str()),
+            GlobKind::Regex(ref regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Translate a glob with character classes to an anchored regular expression.
+fn translate(pattern: &str, case_sensitive: bool) -> Result<Regex, regex::Error> {
+    let mut translated = String::from("^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => translated.push_str(".*"),
+            '?' => translated.push('.'),
+            '[' => {
+                translated.push('[');
+                let class: String = chars.by_ref().take_while(|&c| c != ']').collect();
+                let class = class.strip_prefix('!').map_or(class.clone(), |negated| format!("^{}", negated));
+                translated.push_str(&class.replace('\\', "\\\\").replace('[', "\\["));
+                translated.push(']');
+            }
+            c => translated.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    translated.push('$');
+    RegexBuilder::new(&translated).case_insensitive(!case_sensitive).build()
+}
+
+/// Single test of the file contents, with the tests that must also pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Match {
+    offset: usize,
+    range: usize,
+    value: Vec<u8>,
+    mask: Option<Vec<u8>>,
+    children: Vec<Match>,
+}
+
+impl Match {
+    fn matches(&self, data: &[u8]) -> bool {
+        let found = (self.offset..self.offset + self.range).any(|start| {
+            let window = match data.get(start..start + self.value.len()) {
+                Some(window) => window,
+                None => return false,
+            };
+            match self.mask {
+                None => window == &self.value[..],
+                Some(ref mask) => (0..window.len()).all(|i| window[i] & mask[i] == self.value[i] & mask[i]),
+            }
+        });
+        found && (self.children.is_empty() || self.children.iter().any(|child| child.matches(data)))
+    }
+
+    /// Number of bytes needed for the test.
+    fn extent(&self) -> usize {
+        let own = self.offset + self.range + self.value.len();
+        self.children.iter().map(Match::extent).fold(own, usize::max)
+    }
+}
+
+/// Content tests of a MIME type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Magic {
+    priority: u32,
+    mime: String,
+    matches: Vec<Match>,
+}
+
+/// Reasons for unusable magic file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MagicError(String);
+
+impl fmt::Display for MagicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid magic file: {}", self.0)
+    }
+}
+
+impl error::Error for MagicError {}
+
+/// Reader of the binary magic file.
+struct Cursor<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.position).cloned()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), MagicError> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(MagicError(format!("expected {:?} at offset {}", byte as char, self.position)))
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], MagicError> {
+        let taken = self
+            .data
+            .get(self.position..self.position + length)
+            .ok_or_else(|| MagicError("unexpected end of file".into()))?;
+        self.position += length;
+        Ok(taken)
+    }
+
+    /// Decimal number; `None` if there are no digits.
+    fn number(&mut self) -> Result<Option<usize>, MagicError> {
+        let start = self.position;
+        while self.peek().is_some_and(|byte| byte.is_ascii_digit()) {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Ok(None);
+        }
+        let digits = String::from_utf8_lossy(&self.data[start..self.position]);
+        digits
+            .parse()
+            .map(Some)
+            .map_err(|_| MagicError(format!("number too large at offset {}", start)))
+    }
+
+    /// Skip the rest of the line.
+    fn skip_line(&mut self) {
+        while let Some(byte) = self.peek() {
+            self.position += 1;
+            if byte == b'\n' {
+                break;
+            }
+        }
+    }
+}
+
+/// Parse a single match line; `None` for lines with unknown extensions, which are skipped.
+fn parse_match(cursor: &mut Cursor) -> Result<Option<(usize, Match)>, MagicError> {
+    let indent = cursor.number()?.unwrap_or(0);
+    cursor.expect(b'>')?;
+    let offset = cursor.number()?.ok_or_else(|| MagicError("missing offset".into()))?;
+    cursor.expect(b'=')?;
+    let length = cursor.take(2)?;
+    let length = usize::from(length[0]) << 8 | usize::from(length[1]);
+    let mut value = cursor.take(length)?.to_vec();
+    let mut mask = None;
+    if cursor.eat(b'&') {
+        mask = Some(cursor.take(length)?.to_vec());
+    }
+    let mut word_size = 1;
+    if cursor.eat(b'~') {
+        word_size = cursor.number()?.unwrap_or(1);
+    }
+    let mut range = 1;
+    if cursor.eat(b'+') {
+        range = cursor.number()?.unwrap_or(1);
+    }
+    if !cursor.eat(b'\n') {
+        cursor.skip_line();
+        return Ok(None);
+    }
+
+    // Values are stored big-endian
+    if cfg!(target_endian = "little") && word_size > 1 {
+        value.chunks_mut(word_size).for_each(<[u8]>::reverse);
+        if let Some(ref mut mask) = mask {
+            mask.chunks_mut(word_size).for_each(<[u8]>::reverse);
+        }
+    }
+    Ok(Some((
+        indent,
+        Match {
+            offset,
+            range: range.max(1),
+            value,
+            mask,
+            children: Vec::new(),
+        },
+    )))
+}
+
+/// Parse the contents of a magic file.
+fn parse_magic(data: &[u8]) -> Result<Vec<Magic>, MagicError> {
+    const HEADER: &[u8] = b"MIME-Magic\0\n";
+    if !data.starts_with(HEADER) {
+        return Err(MagicError("missing header".into()));
+    }
+    let mut cursor = Cursor {
+        data,
+        position: HEADER.len(),
+    };
+
+    let mut sections = Vec::new();
+    while cursor.peek().is_some() {
+        cursor.expect(b'[')?;
+        let priority = cursor.number()?.unwrap_or(0) as u32;
+        cursor.expect(b':')?;
+        let start = cursor.position;
+        while cursor.peek().is_some_and(|byte| byte != b']') {
+            cursor.position += 1;
+        }
+        let mime = String::from_utf8_lossy(&data[start..cursor.position]).into_owned();
+        cursor.expect(b']')?;
+        cursor.expect(b'\n')?;
+
+        // Each match is a child of the last one with lower indentation
+        let mut stack: Vec<(usize, Match)> = Vec::new();
+        let mut matches = Vec::new();
+        while cursor.peek().is_some_and(|byte| byte != b'[') {
+            let (indent, current) = match parse_match(&mut cursor)? {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            while stack.last().is_some_and(|&(level, _)| level >= indent) {
+                attach(&mut stack, &mut matches);
+            }
+            stack.push((indent, current));
+        }
+        while !stack.is_empty() {
+            attach(&mut stack, &mut matches);
+        }
+        sections.push(Magic { priority, mime, matches });
+    }
+    Ok(sections)
+}
+
+/// Move the innermost match to its parent, or to the top level.
+fn attach(stack: &mut Vec<(usize, Match)>, top: &mut Vec<Match>) {
+    if let Some((_, child)) = stack.pop() {
+        match stack.last_mut() {
+            Some(&mut (_, ref mut parent)) => parent.children.push(child),
+            None => top.push(child),
+        }
+    }
+}
+
+/// Most bytes read from a file for content detection.
+const MAX_EXTENT: usize = 1 << 20;
+
+/// Type of empty files.
+pub const EMPTY: &str = "application/x-zerosize";
+/// Type of directories.
+pub const DIRECTORY: &str = "inode/directory";
+/// Type of text files of unknown format.
+pub const TEXT: &str = "text/plain";
+/// Type of binary files of unknown format.
+pub const BINARY: &str = "application/octet-stream";
+
+/// MIME type database.
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    globs: Vec<Glob>,
+    magic: Vec<Magic>,
+    parents: BTreeMap<String, Vec<String>>,
+}
+
+impl Database {
+    /// Add file name patterns, in the format of `globs2` (`weight:type:glob[:flags]`)
+    /// or `globs` (`type:glob`, weight 50).
+    pub fn with_globs(mut self, source: &str) -> Database {
+        for line in source.lines().filter(|line| !line.starts_with('#')) {
+            let fields: Vec<&str> = line.split(':').collect();
+            let (weight, fields) = match fields[0].parse() {
+                Ok(weight) => (weight, &fields[1..]),
+                Err(_) => (50, &fields[..]),
+            };
+            if let [mime, pattern, ref flags @ ..] = *fields {
+                let case_sensitive = flags.iter().any(|flags| flags.split(',').any(|flag| flag == "cs"));
+                // Globs unusable for this implementation are skipped, as are malformed lines
+                if let Ok(glob) = Glob::new(weight, mime, pattern, case_sensitive) {
+                    self.globs.push(glob);
+                }
+            }
+        }
+        self
+    }
+
+    /// Add content tests, in the format of the `magic` file.
+    pub fn with_magic(mut self, data: &[u8]) -> Result<Database, MagicError> {
+        self.magic.extend(parse_magic(data)?);
+        self.magic.sort_by_key(|magic| Reverse(magic.priority));
+        Ok(self)
+    }
+
+    /// Add type hierarchy, in the format of `subclasses` (`type parent`).
+    pub fn with_subclasses(mut self, source: &str) -> Database {
+        for line in source.lines() {
+            if let Some((mime, parent)) = line.trim().split_once(' ') {
+                self.parents.entry(mime.into()).or_default().push(parent.trim().into());
+            }
+        }
+        self
+    }
+
+    /// Load the database from the `mime` subdirectories of the XDG data directories.
+    ///
+    /// Missing and invalid files are skipped.
+    pub fn load() -> Database {
+        let mut database = Database::default();
+        for dir in xdg::data_search_path().iter().map(|dir| dir.join("mime")) {
+            let globs = fs::read_to_string(dir.join("globs2")).or_else(|_| fs::read_to_string(dir.join("globs")));
+            if let Ok(globs) = globs {
+                database = database.with_globs(&globs);
+            }
+            if let Ok(magic) = fs::read(dir.join("magic")) {
+                database = database.clone().with_magic(&magic).unwrap_or(database);
+            }
+            if let Ok(subclasses) = fs::read_to_string(dir.join("subclasses")) {
+                database = database.with_subclasses(&subclasses);
+            }
+        }
+        database
+    }
+
+    /// Types for the file name, with the highest weight and longest pattern.
+    fn by_name(&self, name: &str) -> Vec<&str> {
+        let mut best: Option<(u32, usize)> = None;
+        let mut found = Vec::new();
+        for glob in self.globs.iter().filter(|glob| glob.matches(name)) {
+            let key = (glob.weight, glob.length);
+            if best.is_none_or(|best| key > best) {
+                best = Some(key);
+                found.clear();
+            }
+            if best == Some(key) && !found.contains(&glob.mime.as_str()) {
+                found.push(glob.mime.as_str());
+            }
+        }
+        found
+    }
+
+    /// Type of the contents, from the highest priority test that passes.
+    fn by_content(&self, data: &[u8]) -> Option<&str> {
+        self.magic
+            .iter()
+            .find(|magic| magic.matches.iter().any(|test| test.matches(data)))
+            .map(|magic| magic.mime.as_str())
+    }
+
+    /// Determine the type of a file from its name only.
+    pub fn detect_name(&self, name: &str) -> Option<String> {
+        self.by_name(name).first().map(|mime| (*mime).to_owned())
+    }
+
+    /// Determine the type of a file from its name and contents.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// use urldispatch::mime::Database;
+    /// let database = Database::default()
+    ///     .with_globs("50:image/png:*.png\n")
+    ///     .with_magic(b"MIME-Magic\0\n[50:application/pdf]\n>0=\0\x05%PDF-\n")
+    ///     .unwrap();
+    /// assert_eq!(database.detect_data("photo.PNG", b""), "image/png");
+    /// assert_eq!(database.detect_data("document", b"%PDF-1.5"), "application/pdf");
+    /// assert_eq!(database.detect_data("notes", b"plain text"), "text/plain");
+    /// ```
+    pub fn detect_data(&self, name: &str, data: &[u8]) -> String {
+        let by_name = self.by_name(name);
+        if by_name.len() == 1 {
+            return by_name[0].to_owned();
+        }
+        // Contents decide between the types for the name
+        let by_content = self.by_content(data);
+        if let Some(mime) = by_content.filter(|mime| by_name.is_empty() || by_name.contains(mime)) {
+            return mime.to_owned();
+        }
+        if let Some(mime) = by_name.first() {
+            return (*mime).to_owned();
+        }
+
+        let sample = &data[..data.len().min(512)];
+        if data.is_empty() {
+            EMPTY.into()
+        } else if !sample.contains(&0) {
+            TEXT.into()
+        } else {
+            BINARY.into()
+        }
+    }
+
+    /// Determine the type of a local file.
+    pub fn detect<P: AsRef<Path>>(&self, path: P) -> io::Result<String> {
+        let path = path.as_ref();
+        if fs::metadata(path)?.is_dir() {
+            return Ok(DIRECTORY.into());
+        }
+
+        let extent = self.magic.iter().flat_map(|magic| &magic.matches).map(Match::extent);
+        let extent = extent.fold(512, usize::max).min(MAX_EXTENT);
+        let mut data = Vec::with_capacity(extent);
+        File::open(path)?.take(extent as u64).read_to_end(&mut data)?;
+
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        Ok(self.detect_data(&name, &data))
+    }
+
+    /// The type along with all of its supertypes, most specific first.
+    ///
+    /// Besides the explicit hierarchy, all `text/*` types are `text/plain`;
+    /// the implicit `application/octet-stream` supertype of all files is left out.
+    pub fn ancestors(&self, mime: &str) -> Vec<String> {
+        let mut ancestors = vec![mime.to_owned()];
+        let mut next = 0;
+        while next < ancestors.len() {
+            let current = ancestors[next].clone();
+            next += 1;
+            let mut parents = self.parents.get(&current).cloned().unwrap_or_default();
+            if current.starts_with("text/") {
+                parents.push(TEXT.into());
+            }
+            for parent in parents {
+                if !ancestors.contains(&parent) {
+                    ancestors.push(parent);
+                }
+            }
+        }
+        ancestors
+    }
+}
+
+/// The database from the standard locations, loaded on first use.
+pub fn database() -> &'static Database {
+    static DATABASE: OnceLock<Database> = OnceLock::new();
+    DATABASE.get_or_init(Database::load)
+}
+
+/// Type of the local file the URL refers to, along with its supertypes;
+/// empty for other URLs. Inaccessible files are recognized by name only.
+pub fn url_types(url: &Url) -> Vec<String> {
+    let path = match url.to_file_path() {
+        Ok(ref path) if url.scheme() == "file" => path.clone(),
+        _ => return Vec::new(),
+    };
+    let database = database();
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+    database
+        .detect(&path)
+        .ok()
+        .or_else(|| database.detect_name(&name?))
+        .map_or_else(Vec::new, |mime| database.ancestors(&mime))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn patterns_match_types() {
+        let image: MimePattern = "image/*".parse().unwrap();
+        assert!(image.matches("image/png"));
+        assert!(image.matches("Image/JPEG"));
+        assert!(!image.matches("video/mp4"));
+        assert_eq!(image.to_string(), "image/*");
+
+        let pdf: MimePattern = "application/PDF".parse().unwrap();
+        assert!(pdf.matches("application/pdf"));
+        assert!(!pdf.matches("application/pdf+xml"));
+
+        for invalid in &["image", "*/*", "image/", "image/png/x", "text/ plain"] {
+            assert_eq!(invalid.parse::<MimePattern>(), Err(MimePatternError(invalid.to_string())));
+        }
+    }
+
+    #[test]
+    fn globs_match_names() {
+        let database = Database::default().with_globs(
+            "# comment\n50:text/x-c:*.c:cs\n50:text/x-c++:*.C:cs\n50:text/x-makefile:makefile\n\
+             50:application/x-troff-man:*.[1-9]\nimage/jpeg:*.jpg\n60:application/x-tar:*.tar\n\
+             50:application/x-compressed-tar:*.tar.gz\n50:application/gzip:*.gz\n",
+        );
+        assert_eq!(database.by_name("main.c"), ["text/x-c"]);
+        assert_eq!(database.by_name("main.C"), ["text/x-c++"]);
+        assert_eq!(database.by_name("Makefile"), ["text/x-makefile"]);
+        assert_eq!(database.by_name("ls.1"), ["application/x-troff-man"]);
+        assert_eq!(database.by_name("PHOTO.JPG"), ["image/jpeg"]);
+        assert_eq!(database.by_name("a.tar.gz"), ["application/x-compressed-tar"]);
+        assert!(database.by_name("README").is_empty());
+    }
+
+    #[test]
+    fn magic_matches_contents() {
+        let magic = b"MIME-Magic\0\n\
+            [90:application/x-docbook+xml]\n>0=\0\x05<?xml\n1>0=\0\x07DocBook+40\n\
+            [80:text/xml]\n>0=\0\x05<?xml\n\
+            [50:image/png]\n>0=\0\x04\x89PNG\n\
+            [50:audio/x-masked]\n>2=\0\x02AB&\xff\xdf\n\
+            [50:application/x-unknown-extension]\n>0=\0\x01X!future\n>0=\0\x01Y\n";
+        let database = Database::default().with_magic(magic).unwrap();
+
+        assert_eq!(database.by_content(b"<?xml version='1.0'?><!DOCTYPE DocBook>"), Some("application/x-docbook+xml"));
+        assert_eq!(database.by_content(b"<?xml version='1.0'?><svg/>"), Some("text/xml"));
+        assert_eq!(database.by_content(b"\x89PNG\r\n"), Some("image/png"));
+        assert_eq!(database.by_content(b"..Ab"), Some("audio/x-masked"));
+        assert_eq!(database.by_content(b"Y"), Some("application/x-unknown-extension"));
+        assert_eq!(database.by_content(b"X"), None);
+
+        assert!(Database::default().with_magic(b"MIME-Magic\0\n[50:x/y]\n>0=\0\x09short\n").is_err());
+        assert!(Database::default().with_magic(b"not magic").is_err());
+    }
+
+    #[test]
+    fn detects_files() {
+        let database = Database::default()
+            .with_globs("50:text/html:*.html\n50:application/xhtml+xml:*.html\n")
+            .with_magic(b"MIME-Magic\0\n[50:application/xhtml+xml]\n>0=\0\x05<?xml\n")
+            .unwrap()
+            .with_subclasses("application/xhtml+xml application/xml\napplication/xml text/plain\n");
+        assert_eq!(database.detect_data("page.html", b"<?xml version='1.0'?>"), "application/xhtml+xml");
+        assert_eq!(database.detect_data("page.html", b"<html>"), "text/html");
+        assert_eq!(database.detect_data("data", b"\x00\x01"), BINARY);
+        assert_eq!(database.detect_data("data", b""), EMPTY);
+        assert_eq!(database.detect_name("page.HTML").as_deref(), Some("text/html"));
+        assert_eq!(database.detect_name("data"), None);
+        assert_eq!(
+            database.ancestors("application/xhtml+xml"),
+            ["application/xhtml+xml", "application/xml", TEXT]
+        );
+        assert_eq!(database.ancestors("text/html"), ["text/html", TEXT]);
+
+        let dir = env::temp_dir().join(format!("urldispatch-mime-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.html"), "<html>").unwrap();
+        assert_eq!(database.detect(&dir).unwrap(), DIRECTORY);
+        assert_eq!(database.detect(dir.join("index.html")).unwrap(), "text/html");
+        assert!(database.detect(dir.join("missing")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
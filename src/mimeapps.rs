@@ -0,0 +1,327 @@
+//! Default applications, as defined by the freedesktop.org
+//! Association between MIME types and applications specification.
+//!
+//! The associations are read from `mimeapps.list` files in the XDG configuration
+//! and data directories, and from `mimeinfo.cache` files summarizing the `MimeType`
+//! keys of the installed desktop entries.
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::{env, fs};
+
+use xdg;
+
+/// Group with the default applications.
+const DEFAULTS: &str = "Default Applications";
+
+/// Lists of desktop entry IDs, by MIME type.
+type Lists = BTreeMap<String, Vec<String>>;
+
+/// Associations from a single file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Associations {
+    default: Lists,
+    added: Lists,
+    removed: Lists,
+}
+
+impl Associations {
+    /// Parse the contents of a `mimeapps.list` file.
+    ///
+    /// Malformed lines and unknown groups are ignored, as other implementations do.
+    pub fn parse(source: &str) -> Associations {
+        let mut associations = Associations::default();
+        for (group, mime, ids) in entries(source) {
+            let lists = match group {
+                DEFAULTS => &mut associations.default,
+                "Added Associations" => &mut associations.added,
+                "Removed Associations" => &mut associations.removed,
+                _ => continue,
+            };
+            lists.entry(mime.to_owned()).or_default().extend(ids);
+        }
+        associations
+    }
+
+    /// Parse the contents of a `mimeinfo.cache` file; its associations count as added.
+    pub fn parse_cache(source: &str) -> Associations {
+        let mut associations = Associations::default();
+        for (group, mime, ids) in entries(source) {
+            if group == "MIME Cache" {
+                associations.added.entry(mime.to_owned()).or_default().extend(ids);
+            }
+        }
+        associations
+    }
+}
+
+/// Group, key and listed desktop IDs of each entry in the file.
+fn entries(source: &str) -> Vec<(&str, &str, Vec<String>)> {
+    let mut group = "";
+    let mut entries = Vec::new();
+    for line in source.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            group = &line[1..line.len() - 1];
+        } else if let Some((mime, ids)) = line.split_once('=').filter(|_| !line.starts_with('#')) {
+            let ids = ids.split(';').map(str::trim).filter(|id| !id.is_empty()).map(String::from);
+            entries.push((group, mime.trim(), ids.collect()));
+        }
+    }
+    entries
+}
+
+/// Names of `mimeapps.list` files, in order of preference:
+/// the ones specific to `$XDG_CURRENT_DESKTOP` first.
+fn list_names() -> Vec<String> {
+    let desktops = env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+    desktops
+        .split(':')
+        .filter(|desktop| !desktop.is_empty())
+        .map(|desktop| format!("{}-mimeapps.list", desktop.to_lowercase()))
+        .chain(Some("mimeapps.list".to_owned()))
+        .collect()
+}
+
+/// Associations from all files, in order of preference.
+#[derive(Debug, Clone, Default)]
+pub struct MimeApps {
+    files: Vec<Associations>,
+}
+
+impl MimeApps {
+    /// Combine associations from files, in order of preference.
+    pub fn new(files: Vec<Associations>) -> MimeApps {
+        MimeApps { files }
+    }
+
+    /// Read the associations from the standard locations.
+    ///
+    /// Missing and unreadable files are skipped.
+    pub fn load() -> MimeApps {
+        let applications: Vec<PathBuf> = xdg::data_search_path()
+            .into_iter()
+            .map(|dir| dir.join("applications"))
+            .collect();
+        let names = list_names();
+        let lists = xdg::config_search_path()
+            .into_iter()
+            .chain(applications.iter().cloned())
+            .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .map(|source| Associations::parse(&source));
+        let caches = applications
+            .iter()
+            .filter_map(|dir| fs::read_to_string(dir.join("mimeinfo.cache")).ok())
+            .map(|source| Associations::parse_cache(&source));
+
+        MimeApps::new(lists.chain(caches).collect())
+    }
+
+    /// Desktop entry IDs of applications handling the MIME type, in order of preference.
+    ///
+    /// The defaults come first; then the associations not removed
+    /// by the same or a more important file.
+    /// Whether the applications are installed is not checked.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// use urldispatch::mimeapps::{Associations, MimeApps};
+    /// let user = Associations::parse("[Removed Associations]\ntext/html=lynx.desktop;\n");
+    /// let system = Associations::parse(
+    ///     "[Default Applications]\ntext/html=firefox.desktop\n\
+    ///      [Added Associations]\ntext/html=lynx.desktop;chromium.desktop;\n",
+    /// );
+    /// let apps = MimeApps::new(vec![user, system]);
+    /// assert_eq!(apps.handlers("text/html"), ["firefox.desktop", "chromium.desktop"]);
+    /// ```
+    pub fn handlers(&self, mime: &str) -> Vec<String> {
+        let mut handlers: Vec<String> = Vec::new();
+        let mut push = |id: &String| {
+            if !handlers.contains(id) {
+                handlers.push(id.clone());
+            }
+        };
+
+        for file in &self.files {
+            file.default.get(mime).into_iter().flatten().for_each(&mut push);
+        }
+        let mut removed = HashSet::new();
+        for file in &self.files {
+            removed.extend(file.removed.get(mime).into_iter().flatten());
+            file.added
+                .get(mime)
+                .into_iter()
+                .flatten()
+                .filter(|id| !removed.contains(id))
+                .for_each(&mut push);
+        }
+        handlers
+    }
+
+    /// Desktop entry IDs of applications handling the URL scheme, in order of preference.
+    pub fn scheme_handlers(&self, scheme: &str) -> Vec<String> {
+        self.handlers(&format!("x-scheme-handler/{}", scheme))
+    }
+}
+
+/// Format a list entry.
+fn entry(mime: &str, ids: &[String]) -> String {
+    format!("{}={};", mime, ids.join(";"))
+}
+
+/// Edit the lists of default applications in the contents of a `mimeapps.list` file,
+/// keeping the rest of it intact.
+///
+/// The function receives the MIME type with its list and returns the new list;
+/// empty lists are removed. The `missing` MIME types are edited even if not listed in the file.
+fn edit_defaults<F>(source: &str, missing: &[String], mut edit: F) -> String
+where
+    F: FnMut(&str, Vec<String>) -> Vec<String>,
+{
+    let mut lines: Vec<String> = Vec::new();
+    let mut missing: Vec<&String> = missing.iter().collect();
+    let mut group = "";
+    // Where to add the missing entries: after the last line of the defaults group
+    let mut end = None;
+
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            group = &trimmed[1..trimmed.len() - 1];
+        } else if group == DEFAULTS && !trimmed.starts_with('#') {
+            if let Some((mime, ids)) = trimmed.split_once('=') {
+                let mime = mime.trim();
+                missing.retain(|other| *other != mime);
+                let ids = ids.split(';').map(str::trim).filter(|id| !id.is_empty()).map(String::from);
+                let ids = edit(mime, ids.collect());
+                if !ids.is_empty() {
+                    lines.push(entry(mime, &ids));
+                }
+                end = Some(lines.len());
+                continue;
+            }
+        }
+        lines.push(line.to_owned());
+        if group == DEFAULTS && !trimmed.is_empty() {
+            end = Some(lines.len());
+        }
+    }
+
+    let added: Vec<String> = missing
+        .into_iter()
+        .map(|mime| (mime, edit(mime, Vec::new())))
+        .filter(|(_, ids)| !ids.is_empty())
+        .map(|(mime, ids)| entry(mime, &ids))
+        .collect();
+    match end {
+        Some(end) => {
+            lines.splice(end..end, added);
+        }
+        None if !added.is_empty() => {
+            if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{}]", DEFAULTS));
+            lines.extend(added);
+        }
+        None => {}
+    }
+
+    let mut edited = lines.join("\n");
+    if !edited.is_empty() {
+        edited.push('\n');
+    }
+    edited
+}
+
+/// Make the desktop entry the default application for the MIME types
+/// in the contents of a `mimeapps.list` file.
+///
+/// The previous defaults are kept in the list as less preferred,
+/// so that [`unset_default`](fn.unset_default.html) can restore them.
+pub fn set_default(source: &str, id: &str, mimes: &[String]) -> String {
+    edit_defaults(source, mimes, |mime, ids| {
+        if !mimes.iter().any(|other| other == mime) {
+            return ids;
+        }
+        let others = ids.into_iter().filter(|other| other != id);
+        Some(id.to_owned()).into_iter().chain(others).collect()
+    })
+}
+
+/// Remove the desktop entry from the default applications
+/// in the contents of a `mimeapps.list` file.
+pub fn unset_default(source: &str, id: &str) -> String {
+    edit_defaults(source, &[], |_, ids| ids.into_iter().filter(|other| other != id).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_associations() {
+        let associations = Associations::parse(
+            "# comment\n[Default Applications]\nx-scheme-handler/https = firefox.desktop;\n\
+             [Added Associations]\ntext/html=firefox.desktop;chromium.desktop;\nmalformed\n\
+             [Removed Associations]\ntext/html=lynx.desktop\n[Other]\ntext/plain=vim.desktop\n",
+        );
+        assert_eq!(associations.default["x-scheme-handler/https"], ["firefox.desktop"]);
+        assert_eq!(associations.added["text/html"], ["firefox.desktop", "chromium.desktop"]);
+        assert_eq!(associations.removed["text/html"], ["lynx.desktop"]);
+        assert!(!associations.added.contains_key("text/plain"));
+
+        let cache = Associations::parse_cache("[MIME Cache]\ntext/plain=vim.desktop;gedit.desktop;\n");
+        assert_eq!(cache.added["text/plain"], ["vim.desktop", "gedit.desktop"]);
+    }
+
+    #[test]
+    fn edits_defaults() {
+        let source = "[Added Associations]\ntext/html=firefox.desktop;\n\n\
+                      [Default Applications]\n# comment\nx-scheme-handler/http=firefox.desktop\n\
+                      text/html=firefox.desktop;\n\n[Removed Associations]\n";
+        let mimes = vec!["x-scheme-handler/http".to_owned(), "x-scheme-handler/https".to_owned()];
+
+        let set = set_default(source, "urldispatch.desktop", &mimes);
+        assert_eq!(
+            set,
+            "[Added Associations]\ntext/html=firefox.desktop;\n\n\
+             [Default Applications]\n# comment\nx-scheme-handler/http=urldispatch.desktop;firefox.desktop;\n\
+             text/html=firefox.desktop;\nx-scheme-handler/https=urldispatch.desktop;\n\n[Removed Associations]\n"
+        );
+        assert_eq!(set_default(&set, "urldispatch.desktop", &mimes), set);
+        assert_eq!(
+            unset_default(&set, "urldispatch.desktop"),
+            "[Added Associations]\ntext/html=firefox.desktop;\n\n\
+             [Default Applications]\n# comment\nx-scheme-handler/http=firefox.desktop;\n\
+             text/html=firefox.desktop;\n\n[Removed Associations]\n"
+        );
+
+        let created = set_default("", "urldispatch.desktop", &mimes[..1]);
+        assert_eq!(created, "[Default Applications]\nx-scheme-handler/http=urldispatch.desktop;\n");
+        assert_eq!(unset_default(&created, "urldispatch.desktop"), "[Default Applications]\n");
+    }
+
+    #[test]
+    fn orders_handlers() {
+        let apps = MimeApps::new(vec![
+            Associations::parse(
+                "[Added Associations]\nx-scheme-handler/https=qutebrowser.desktop\n\
+                 [Removed Associations]\nx-scheme-handler/https=lynx.desktop\n",
+            ),
+            Associations::parse(
+                "[Default Applications]\nx-scheme-handler/https=firefox.desktop\n\
+                 [Added Associations]\nx-scheme-handler/https=lynx.desktop;firefox.desktop\n",
+            ),
+            Associations::parse_cache("[MIME Cache]\nx-scheme-handler/https=chromium.desktop;lynx.desktop\n"),
+        ]);
+        assert_eq!(
+            apps.scheme_handlers("https"),
+            ["firefox.desktop", "qutebrowser.desktop", "chromium.desktop"]
+        );
+        assert!(apps.scheme_handlers("gopher").is_empty());
+    }
+}
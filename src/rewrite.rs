@@ -0,0 +1,363 @@
+//! Rewriting of URLs before the launcher is selected.
+
+use std::{error, fmt};
+
+use regex::{self, Regex};
+use url::{form_urlencoded, Host, Url};
+
+use rules::{self, Check, Conditions};
+
+/// Default limit of rewrite steps for a single URL.
+pub const MAX_STEPS: usize = 16;
+
+/// Transformation of an URL.
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Remove query parameters with names matching any of the shell-like globs;
+    /// the globs are kept along with their translation for display purposes.
+    StripParams(Vec<String>, Regex),
+    /// Replace the URL with the value of a query parameter, as passed to redirectors.
+    Unwrap(String),
+    /// Replace the host name.
+    SetHost(String),
+}
+
+impl Action {
+    /// Remove query parameters with names matching any of the globs.
+    pub fn strip_params<I, S>(globs: I) -> Result<Action, regex::Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let globs: Vec<String> = globs.into_iter().map(Into::into).collect();
+        let patterns: Vec<&str> = globs.iter().map(String::as_str).collect();
+        rules::glob(&patterns).map(|regex| Action::StripParams(globs, regex))
+    }
+
+    /// Replace the host name; fails if the host name is not valid.
+    pub fn set_host<S: Into<String>>(host: S) -> Result<Action, url::ParseError> {
+        let host = host.into();
+        Host::parse(&host)?;
+        Ok(Action::SetHost(host))
+    }
+
+    /// Apply the transformation; `None` if it does not change the URL.
+    pub fn apply(&self, url: &Url) -> Option<Url> {
+        let rewritten = match *self {
+            Action::StripParams(_, ref regex) => {
+                // Kept parameters are copied as they are, without encoding them again
+                let query = url.query()?;
+                let pieces: Vec<&str> = query.split('&').collect();
+                let kept: Vec<&str> = pieces
+                    .iter()
+                    .copied()
+                    .filter(|piece| match form_urlencoded::parse(piece.as_bytes()).next() {
+                        Some((name, _)) => !regex.is_match(&name),
+                        None => true,
+                    })
+                    .collect();
+                if kept.len() == pieces.len() {
+                    return None;
+                }
+
+                let mut rewritten = url.clone();
+                if kept.is_empty() {
+                    rewritten.set_query(None);
+                } else {
+                    rewritten.set_query(Some(&kept.join("&")));
+                }
+                rewritten
+            }
+            Action::Unwrap(ref param) => {
+                let (_, target) = url.query_pairs().find(|(name, _)| name == param)?;
+                Url::parse(&target).ok()?
+            }
+            Action::SetHost(ref host) => {
+                let mut rewritten = url.clone();
+                rewritten.set_host(Some(host)).ok()?;
+                rewritten
+            }
+        };
+
+        Some(rewritten).filter(|rewritten| rewritten != url)
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Action::StripParams(ref globs, _) => write!(f, "strip parameters {}", globs.join(", ")),
+            Action::Unwrap(ref param) => write!(f, "unwrap parameter {}", param),
+            Action::SetHost(ref host) => write!(f, "set host to {}", host),
+        }
+    }
+}
+
+/// Named transformation of URLs meeting its conditions.
+#[derive(Debug, Clone)]
+pub struct Rewrite {
+    name: String,
+    conditions: Conditions,
+    action: Action,
+}
+
+impl Rewrite {
+    /// Create a rewrite applied to all URLs.
+    pub fn new<S: Into<String>>(name: S, action: Action) -> Rewrite {
+        Rewrite {
+            name: name.into(),
+            conditions: Conditions::default(),
+            action,
+        }
+    }
+
+    /// Apply only to URLs meeting the conditions.
+    pub fn with_conditions(mut self, conditions: Conditions) -> Rewrite {
+        self.conditions = conditions;
+        self
+    }
+
+    /// Name of the rewrite.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Transformation of the matching URLs.
+    pub fn action(&self) -> &Action {
+        &self.action
+    }
+
+    /// Evaluate each condition separately, to explain why the rewrite applies.
+    pub fn explain(&self, url: &Url) -> Vec<Check> {
+        self.conditions.explain(url)
+    }
+
+    /// Rewrite the URL; `None` if the rewrite does not apply or change it.
+    pub fn apply(&self, url: &Url) -> Option<Url> {
+        if self.conditions.matches(url) {
+            self.action.apply(url)
+        } else {
+            None
+        }
+    }
+}
+
+/// Single applied rewrite.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// Name of the rewrite.
+    pub rewrite: String,
+    /// The URL after the rewrite.
+    pub url: Url,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rewrite {}: {}", self.rewrite, self.url)
+    }
+}
+
+/// Reasons for failed rewriting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewriteError {
+    /// The rewrites did not settle within the step limit; the steps made are included.
+    Loop(Vec<Step>),
+}
+
+impl fmt::Display for RewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RewriteError::Loop(ref steps) => {
+                write!(f, "rewrites did not settle after {} steps:", steps.len())?;
+                for step in steps {
+                    write!(f, " {}", step.rewrite)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for RewriteError {}
+
+/// Outcome of rewriting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewritten {
+    /// The final URL.
+    pub url: Url,
+    /// Applied rewrites, in order.
+    pub steps: Vec<Step>,
+}
+
+/// Ordered collection of rewrites.
+///
+/// The rewrites are applied in order, repeatedly, until none of them changes the URL;
+/// thus a rewrite may see the output of any other one.
+#[derive(Debug, Clone)]
+pub struct Rewriter {
+    rewrites: Vec<Rewrite>,
+    limit: usize,
+}
+
+impl Default for Rewriter {
+    fn default() -> Rewriter {
+        Rewriter::new(Vec::new())
+    }
+}
+
+impl Rewriter {
+    /// Create a rewriter with the default step limit.
+    pub fn new(rewrites: Vec<Rewrite>) -> Rewriter {
+        Rewriter {
+            rewrites,
+            limit: MAX_STEPS,
+        }
+    }
+
+    /// Change the maximum number of steps for a single URL.
+    pub fn with_limit(mut self, limit: usize) -> Rewriter {
+        self.limit = limit;
+        self
+    }
+
+    /// The rewrites, in order of application.
+    pub fn rewrites(&self) -> &[Rewrite] {
+        &self.rewrites
+    }
+
+    /// Rewrite the URL until it settles.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate urldispatch;
+    /// use urldispatch::rewrite::{Action, Rewrite, Rewriter};
+    /// let rewriter = Rewriter::new(vec![
+    ///     Rewrite::new("tracking", Action::strip_params(vec!["utm_*"]).unwrap()),
+    ///     Rewrite::new("redirect", Action::Unwrap("q".into())),
+    /// ]);
+    /// let url = url::Url::parse("https://example.com/?q=https://example.org/%3Futm_source%3Dx").unwrap();
+    /// let rewritten = rewriter.rewrite(&url).expect("Rewrite loop!");
+    /// assert_eq!(rewritten.url.as_str(), "https://example.org/");
+    /// assert_eq!(rewritten.steps.len(), 2);
+    /// ```
+    pub fn rewrite(&self, url: &Url) -> Result<Rewritten, RewriteError> {
+        let mut url = url.clone();
+        let mut steps = Vec::new();
+
+        loop {
+            let mut changed = false;
+            for rewrite in &self.rewrites {
+                if let Some(rewritten) = rewrite.apply(&url) {
+                    if steps.len() == self.limit {
+                        return Err(RewriteError::Loop(steps));
+                    }
+                    url = rewritten;
+                    steps.push(Step {
+                        rewrite: rewrite.name().to_owned(),
+                        url: url.clone(),
+                    });
+                    changed = true;
+                }
+            }
+            if !changed {
+                return Ok(Rewritten { url, steps });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn url(input: &str) -> Url {
+        Url::parse(input).unwrap()
+    }
+
+    #[test]
+    fn actions_rewrite_urls() {
+        let strip = Action::strip_params(vec!["utm_*", "fbclid"]).unwrap();
+        assert_eq!(
+            strip.apply(&url("https://example.com/a?utm_source=x&id=1&fbclid=y")),
+            Some(url("https://example.com/a?id=1"))
+        );
+        assert_eq!(
+            strip.apply(&url("https://example.com/a?utm_medium=x")),
+            Some(url("https://example.com/a"))
+        );
+        assert_eq!(strip.apply(&url("https://example.com/a?id=1")), None);
+        assert_eq!(
+            strip.apply(&url("https://example.com/a?q=a%20b%2Bc&utm_source=x&tag=c++")).unwrap().as_str(),
+            "https://example.com/a?q=a%20b%2Bc&tag=c++"
+        );
+        assert_eq!(strip.to_string(), "strip parameters utm_*, fbclid");
+
+        let unwrap = Action::Unwrap("q".into());
+        assert_eq!(
+            unwrap.apply(&url("https://www.google.com/url?q=https%3A%2F%2Fexample.com%2Fa&sa=D")),
+            Some(url("https://example.com/a"))
+        );
+        assert_eq!(unwrap.apply(&url("https://www.google.com/url?q=not+an+url")), None);
+        assert_eq!(unwrap.apply(&url("https://www.google.com/url")), None);
+
+        let host = Action::set_host("nitter.net").unwrap();
+        assert_eq!(
+            host.apply(&url("https://twitter.com/user/status/1")),
+            Some(url("https://nitter.net/user/status/1"))
+        );
+        assert_eq!(host.apply(&url("https://nitter.net/user")), None);
+        assert!(Action::set_host("not a host").is_err());
+    }
+
+    #[test]
+    fn rewrites_apply_to_matching_urls() {
+        let nitter = Rewrite::new("nitter", Action::set_host("nitter.net").unwrap())
+            .with_conditions(Conditions::default().with_host(".twitter.com".parse().unwrap()));
+        assert_eq!(
+            nitter.apply(&url("https://mobile.twitter.com/user")),
+            Some(url("https://nitter.net/user"))
+        );
+        assert_eq!(nitter.apply(&url("https://example.com/user")), None);
+    }
+
+    #[test]
+    fn rewriter_chains_until_settled() {
+        let rewriter = Rewriter::new(vec![
+            Rewrite::new("tracking", Action::strip_params(vec!["utm_*"]).unwrap()),
+            Rewrite::new("nitter", Action::set_host("nitter.net").unwrap()).with_conditions(
+                Conditions::default().with_host(".twitter.com".parse().unwrap()),
+            ),
+            Rewrite::new("redirect", Action::Unwrap("q".into()))
+                .with_conditions(Conditions::default().with_path("/url")),
+        ]);
+
+        let rewritten = rewriter
+            .rewrite(&url("https://google.com/url?q=https://twitter.com/a%3Futm_source%3Dx"))
+            .unwrap();
+        assert_eq!(rewritten.url, url("https://nitter.net/a"));
+        let names: Vec<_> = rewritten.steps.iter().map(|step| step.rewrite.as_str()).collect();
+        assert_eq!(names, ["redirect", "tracking", "nitter"]);
+
+        let unchanged = rewriter.rewrite(&url("https://example.com/")).unwrap();
+        assert_eq!(unchanged.url, url("https://example.com/"));
+        assert!(unchanged.steps.is_empty());
+    }
+
+    #[test]
+    fn rewriter_stops_loops() {
+        let rewriter = Rewriter::new(vec![
+            Rewrite::new("there", Action::set_host("b.example").unwrap()),
+            Rewrite::new("back", Action::set_host("a.example").unwrap()),
+        ])
+        .with_limit(5);
+
+        match rewriter.rewrite(&url("https://a.example/")) {
+            Err(RewriteError::Loop(ref steps)) if steps.len() == 5 => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
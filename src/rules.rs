@@ -0,0 +1,1087 @@
+//! Selection of launchers based on URL properties.
+
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::{error, fmt, iter, process};
+
+use nix::unistd::Pid;
+use regex::{self, Regex};
+use url::Url;
+
+use desktop::DesktopError;
+use install;
+use launcher::{Launcher, LauncherError};
+use mime::{self, MimePattern};
+use mimeapps::MimeApps;
+use rewrite::{RewriteError, Rewriter};
+use sh;
+use template::{Template, TemplateError};
+
+/// Reasons for failed dispatch of an URL.
+#[derive(Debug)]
+pub enum DispatchError {
+    /// No rule matched the URL.
+    NoMatch(String),
+    /// The launcher command could not be prepared.
+    Template(TemplateError),
+    /// The desktop entry of the launcher is unusable.
+    Desktop(DesktopError),
+    /// The launcher command could not be started.
+    Launch(::Error),
+    /// None of the launchers in the fallback chain was found.
+    Exhausted(Vec<Attempt>),
+    /// The user did not choose any launcher.
+    Cancelled,
+    /// The user chose something that is not a launcher of the rule.
+    UnknownChoice(String),
+    /// The URL could not be rewritten.
+    Rewrite(RewriteError),
+    /// The launcher options cannot be honoured by its backend.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DispatchError::NoMatch(ref url) => write!(f, "no rule matches {}", url),
+            DispatchError::Template(ref err) => err.fmt(f),
+            DispatchError::Desktop(ref err) => err.fmt(f),
+            DispatchError::Launch(ref err) => err.fmt(f),
+            DispatchError::Exhausted(ref attempts) => {
+                f.write_str("no launcher available")?;
+                let mut separator = ": ";
+                for attempt in attempts {
+                    write!(f, "{}{}", separator, attempt)?;
+                    separator = "; ";
+                }
+                Ok(())
+            }
+            DispatchError::Cancelled => f.write_str("no launcher chosen"),
+            DispatchError::UnknownChoice(ref choice) => write!(f, "unknown launcher chosen: {}", choice),
+            DispatchError::Rewrite(ref err) => err.fmt(f),
+            DispatchError::Unsupported(message) => f.write_str(message),
+        }
+    }
+}
+
+impl error::Error for DispatchError {}
+
+impl From<TemplateError> for DispatchError {
+    fn from(err: TemplateError) -> DispatchError {
+        DispatchError::Template(err)
+    }
+}
+impl From<LauncherError> for DispatchError {
+    fn from(err: LauncherError) -> DispatchError {
+        match err {
+            LauncherError::Template(err) => DispatchError::Template(err),
+            LauncherError::Desktop(err) => DispatchError::Desktop(err),
+            LauncherError::Launch(err) => DispatchError::Launch(err),
+            LauncherError::Unsupported(message) => DispatchError::Unsupported(message),
+        }
+    }
+}
+impl From<::Error> for DispatchError {
+    fn from(err: ::Error) -> DispatchError {
+        DispatchError::Launch(err)
+    }
+}
+impl From<RewriteError> for DispatchError {
+    fn from(err: RewriteError) -> DispatchError {
+        DispatchError::Rewrite(err)
+    }
+}
+
+/// Failed attempt to start a launcher.
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    /// Name of the launcher.
+    pub launcher: String,
+    /// Reason of the failure.
+    pub error: ::Error,
+}
+
+impl fmt::Display for Attempt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "launcher {}: {}", self.launcher, self.error)
+    }
+}
+
+/// Description of a successfully dispatched URL.
+#[derive(Debug, Clone)]
+pub struct Dispatched {
+    /// The opened URL.
+    pub url: Url,
+    /// PID of the started launcher.
+    pub pid: Pid,
+    /// Arguments of the started launcher.
+    pub argv: Vec<String>,
+    /// Name of the selected rule.
+    pub rule: String,
+    /// Name of the started launcher.
+    pub launcher: String,
+    /// Launchers tried before the started one, in order.
+    pub failures: Vec<Attempt>,
+}
+
+/// Launcher to try, along with its running instance, looked up once when first needed.
+struct Candidate<'l> {
+    launcher: &'l Launcher,
+    remote: OnceCell<Option<&'l Template>>,
+}
+
+impl<'l> Candidate<'l> {
+    fn new(launcher: &'l Launcher) -> Candidate<'l> {
+        Candidate {
+            launcher,
+            remote: OnceCell::new(),
+        }
+    }
+
+    /// Template of the remote command, if an instance of the program is running.
+    fn remote(&self) -> Option<&'l Template> {
+        *self.remote.get_or_init(|| self.launcher.running_remote())
+    }
+}
+
+/// Report the failed attempts at the end of the fallback chain.
+fn exhausted(mut failures: Vec<Attempt>) -> DispatchError {
+    match failures.len() {
+        1 => failures.remove(0).error.into(),
+        _ => DispatchError::Exhausted(failures),
+    }
+}
+
+/// Translate shell-like globs to an anchored regular expression matching any of them.
+pub(crate) fn glob(patterns: &[&str]) -> Result<Regex, regex::Error> {
+    let mut translated = String::from("^(?:");
+    for (n, pattern) in patterns.iter().enumerate() {
+        if n > 0 {
+            translated.push('|');
+        }
+        for c in pattern.chars() {
+            match c {
+                '*' => translated.push_str(".*"),
+                '?' => translated.push('.'),
+                c => translated.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+    }
+    translated.push_str(")$");
+    Regex::new(&translated)
+}
+
+/// Pattern for matching host names.
+#[derive(Debug, Clone)]
+pub enum HostPattern {
+    /// Host name must be exactly the same.
+    Exact(String),
+    /// Host name must be the same, or a subdomain of the pattern.
+    Suffix(String),
+    /// Host name must match a shell-like glob; `*` matches any sequence
+    /// of characters (including dots), `?` matches any single character.
+    /// The glob is kept along with its translation for display purposes.
+    Glob(String, Regex),
+}
+
+impl HostPattern {
+    /// Check if the host name matches the pattern.
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        match *self {
+            HostPattern::Exact(ref name) => host == *name,
+            HostPattern::Suffix(ref domain) => {
+                host == *domain
+                    || (host.ends_with(domain.as_str())
+                        && host[..host.len() - domain.len()].ends_with('.'))
+            }
+            HostPattern::Glob(_, ref regex) => regex.is_match(&host),
+        }
+    }
+}
+
+impl fmt::Display for HostPattern {
+    /// Format the pattern in the same form it is parsed from.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HostPattern::Exact(ref name) => f.write_str(name),
+            HostPattern::Suffix(ref domain) => write!(f, ".{}", domain),
+            HostPattern::Glob(ref glob, _) => f.write_str(glob),
+        }
+    }
+}
+
+impl FromStr for HostPattern {
+    type Err = regex::Error;
+
+    /// Interpret the pattern based on its form:
+    ///
+    /// -   `.example.com` is a suffix pattern matching `example.com` and its subdomains,
+    /// -   patterns containing `*` or `?` are globs,
+    /// -   everything else is matched exactly.
+    fn from_str(pattern: &str) -> Result<HostPattern, regex::Error> {
+        let pattern = pattern.to_lowercase();
+
+        if let Some(domain) = pattern.strip_prefix('.') {
+            Ok(HostPattern::Suffix(domain.to_owned()))
+        } else if pattern.contains(['*', '?']) {
+            glob(&[&pattern]).map(|regex| HostPattern::Glob(pattern, regex))
+        } else {
+            Ok(HostPattern::Exact(pattern))
+        }
+    }
+}
+
+/// Outcome of a single rule condition, for diagnostic purposes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    /// Human-readable description of the condition.
+    pub condition: String,
+    /// Whether the condition was satisfied.
+    pub satisfied: bool,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verdict = if self.satisfied { "yes" } else { "no" };
+        write!(f, "{}: {}", self.condition, verdict)
+    }
+}
+
+/// Conditions on URL properties.
+///
+/// The conditions are met if all of them are satisfied;
+/// no conditions at all are met by every URL.
+#[derive(Debug, Clone, Default)]
+pub struct Conditions {
+    scheme: Option<String>,
+    host: Option<HostPattern>,
+    path: Option<String>,
+    regex: Option<Regex>,
+    mime: Option<MimePattern>,
+}
+
+impl Conditions {
+    /// Require the URL scheme (case-insensitive).
+    pub fn with_scheme<S: Into<String>>(mut self, scheme: S) -> Conditions {
+        self.scheme = Some(scheme.into().to_lowercase());
+        self
+    }
+
+    /// Require the URL host to match a pattern.
+    pub fn with_host(mut self, pattern: HostPattern) -> Conditions {
+        self.host = Some(pattern);
+        self
+    }
+
+    /// Require the URL path to start with a prefix.
+    pub fn with_path<S: Into<String>>(mut self, prefix: S) -> Conditions {
+        self.path = Some(prefix.into());
+        self
+    }
+
+    /// Require the whole URL to match a regular expression.
+    pub fn with_regex(mut self, regex: Regex) -> Conditions {
+        self.regex = Some(regex);
+        self
+    }
+
+    /// Require the URL to refer to a local file of a type matching the pattern.
+    pub fn with_mime(mut self, pattern: MimePattern) -> Conditions {
+        self.mime = Some(pattern);
+        self
+    }
+
+    /// Check if the URL satisfies all conditions.
+    pub fn matches(&self, url: &Url) -> bool {
+        let scheme = self.scheme.as_ref().is_none_or(|s| url.scheme() == s);
+        let host = self.host.as_ref().is_none_or(|pattern| {
+            url.host_str().is_some_and(|host| pattern.matches(host))
+        });
+        let path = self.path.as_ref().is_none_or(|p| url.path().starts_with(p.as_str()));
+        let regex = self.regex.as_ref().is_none_or(|r| r.is_match(url.as_str()));
+        if !(scheme && host && path && regex) {
+            return false;
+        }
+
+        // Last, as it reads the file
+        self.mime
+            .as_ref()
+            .is_none_or(|pattern| mime::url_types(url).iter().any(|mime| pattern.matches(mime)))
+    }
+
+    /// Evaluate each condition separately, to explain the match result.
+    pub fn explain(&self, url: &Url) -> Vec<Check> {
+        let mut checks = Vec::new();
+        let mut check = |condition: String, satisfied| checks.push(Check { condition, satisfied });
+
+        if let Some(ref scheme) = self.scheme {
+            check(format!("scheme is {}", scheme), url.scheme() == scheme);
+        }
+        if let Some(ref pattern) = self.host {
+            check(
+                format!("host matches {}", pattern),
+                url.host_str().is_some_and(|host| pattern.matches(host)),
+            );
+        }
+        if let Some(ref prefix) = self.path {
+            check(format!("path starts with {}", prefix), url.path().starts_with(prefix.as_str()));
+        }
+        if let Some(ref regex) = self.regex {
+            check(format!("URL matches /{}/", regex), regex.is_match(url.as_str()));
+        }
+        if let Some(ref pattern) = self.mime {
+            let types = mime::url_types(url);
+            check(
+                format!("MIME type matches {}", pattern),
+                types.iter().any(|mime| pattern.matches(mime)),
+            );
+        }
+
+        checks
+    }
+}
+
+/// Launcher selection rule.
+///
+/// A rule matches an URL if all of its conditions are met;
+/// rule without any conditions matches everything.
+///
+/// If the launcher program is not found, the fallback launchers are tried in order.
+/// Alternatively, the user can choose any of the launchers from a menu.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    name: String,
+    conditions: Conditions,
+    launcher: Launcher,
+    fallbacks: Vec<Launcher>,
+    chooser: Option<Template>,
+}
+
+impl Rule {
+    /// Create a rule without any conditions.
+    pub fn new<S: Into<String>>(name: S, launcher: Launcher) -> Rule {
+        Rule {
+            name: name.into(),
+            conditions: Conditions::default(),
+            launcher,
+            fallbacks: Vec::new(),
+            chooser: None,
+        }
+    }
+
+    /// Replace all conditions at once.
+    pub fn with_conditions(mut self, conditions: Conditions) -> Rule {
+        self.conditions = conditions;
+        self
+    }
+
+    /// Require the URL scheme (case-insensitive).
+    pub fn with_scheme<S: Into<String>>(mut self, scheme: S) -> Rule {
+        self.conditions = self.conditions.with_scheme(scheme);
+        self
+    }
+
+    /// Require the URL host to match a pattern.
+    pub fn with_host(mut self, pattern: HostPattern) -> Rule {
+        self.conditions = self.conditions.with_host(pattern);
+        self
+    }
+
+    /// Require the URL path to start with a prefix.
+    pub fn with_path<S: Into<String>>(mut self, prefix: S) -> Rule {
+        self.conditions = self.conditions.with_path(prefix);
+        self
+    }
+
+    /// Require the whole URL to match a regular expression.
+    pub fn with_regex(mut self, regex: Regex) -> Rule {
+        self.conditions = self.conditions.with_regex(regex);
+        self
+    }
+
+    /// Require the URL to refer to a local file of a type matching the pattern.
+    pub fn with_mime(mut self, pattern: MimePattern) -> Rule {
+        self.conditions = self.conditions.with_mime(pattern);
+        self
+    }
+
+    /// Append a launcher to the fallback chain.
+    pub fn with_fallback(mut self, launcher: Launcher) -> Rule {
+        self.fallbacks.push(launcher);
+        self
+    }
+
+    /// Let the user choose the launcher with a menu command
+    /// instead of trying the launchers in order.
+    ///
+    /// The menu reads launcher names, one per line, from its standard input,
+    /// and prints the chosen one to its standard output; `dmenu`, `rofi -dmenu`
+    /// or `fzf` all work that way.
+    pub fn with_chooser(mut self, menu: Template) -> Rule {
+        self.chooser = Some(menu);
+        self
+    }
+
+    /// Name of the rule.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Launcher for the matching URLs.
+    pub fn launcher(&self) -> &Launcher {
+        &self.launcher
+    }
+
+    /// Launchers to try if the preferred one is not found, in order.
+    pub fn fallbacks(&self) -> &[Launcher] {
+        &self.fallbacks
+    }
+
+    /// Menu command for choosing the launcher, if any.
+    pub fn chooser(&self) -> Option<&Template> {
+        self.chooser.as_ref()
+    }
+
+    /// All launchers of the rule, in order of preference.
+    pub fn launchers(&self) -> impl Iterator<Item = &Launcher> {
+        iter::once(&self.launcher).chain(&self.fallbacks)
+    }
+
+    /// Check if the URL satisfies all conditions.
+    pub fn matches(&self, url: &Url) -> bool {
+        self.conditions.matches(url)
+    }
+
+    /// Evaluate each condition separately, to explain the match result.
+    pub fn explain(&self, url: &Url) -> Vec<Check> {
+        self.conditions.explain(url)
+    }
+
+    /// Let the user choose one of the launchers with the menu.
+    fn choose(&self, menu: &Template, url: &Url) -> Result<&Launcher, DispatchError> {
+        let mut names = String::new();
+        for launcher in self.launchers() {
+            names.push_str(launcher.name());
+            names.push('\n');
+        }
+
+        let output = sh::capture(menu.command(url)?, names.as_bytes())?;
+        let choice = String::from_utf8_lossy(&output.stdout);
+        let choice = choice.trim_end_matches(['\r', '\n']);
+        if !output.status.success() || choice.is_empty() {
+            return Err(DispatchError::Cancelled);
+        }
+        self.launchers()
+            .find(|launcher| launcher.name() == choice)
+            .ok_or_else(|| DispatchError::UnknownChoice(choice.to_owned()))
+    }
+
+    /// Start a single launcher.
+    fn launch(&self, candidate: &Candidate, url: &Url) -> Result<Dispatched, DispatchError> {
+        let argv = candidate.launcher.argv(url, candidate.remote())?;
+        self.start(candidate.launcher, argv, url)
+    }
+
+    /// Start a single launcher with the prepared arguments.
+    fn start(&self, launcher: &Launcher, argv: Vec<String>, url: &Url) -> Result<Dispatched, DispatchError> {
+        let command = launcher.prepare(&argv).ok_or(TemplateError::Empty)?;
+
+        Ok(Dispatched {
+            url: url.clone(),
+            pid: launcher.start(command)?,
+            argv,
+            rule: self.name.clone(),
+            launcher: launcher.name().to_owned(),
+            failures: Vec::new(),
+        })
+    }
+
+    /// Open the URL with the chosen launcher, or the first one that is found.
+    ///
+    /// Only missing programs cause the fallbacks to be tried;
+    /// any other failure is reported immediately.
+    pub fn dispatch(&self, url: &Url) -> Result<Dispatched, DispatchError> {
+        if let Some(ref menu) = self.chooser {
+            return self.launch(&Candidate::new(self.choose(menu, url)?), url);
+        }
+
+        let candidates: Vec<_> = self.launchers().map(Candidate::new).collect();
+        self.dispatch_from(&candidates, Vec::new(), url)
+    }
+
+    /// Open the URL with the first of the launchers that is found,
+    /// after the already failed attempts.
+    fn dispatch_from(
+        &self,
+        candidates: &[Candidate],
+        mut failures: Vec<Attempt>,
+        url: &Url,
+    ) -> Result<Dispatched, DispatchError> {
+        for candidate in candidates {
+            match self.launch(candidate, url) {
+                Ok(dispatched) => return Ok(Dispatched { failures, ..dispatched }),
+                Err(DispatchError::Launch(error @ ::Error::NotFound(_))) => failures.push(Attempt {
+                    launcher: candidate.launcher.name().to_owned(),
+                    error,
+                }),
+                Err(error) => return Err(error),
+            }
+        }
+        Err(exhausted(failures))
+    }
+
+    /// Open all the URLs like [`dispatch`](#method.dispatch); returns the outcome
+    /// for each URL, in order.
+    ///
+    /// Launchers opening multiple URLs at once, such as templates with `%U`,
+    /// are started once for all of them; the others once for each URL,
+    /// as are all the URLs if some of them cannot be opened together,
+    /// like web pages by a template with `%F`.
+    /// The menu, if any, is shown only once, for the first URL;
+    /// errors affecting all the URLs at once are shared by their outcomes.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate urldispatch;
+    /// use urldispatch::launcher::Launcher;
+    /// use urldispatch::rules::Rule;
+    /// let rule = Rule::new("web", Launcher::new("true", "true %U".parse().unwrap()));
+    /// let urls = [
+    ///     url::Url::parse("https://example.com").unwrap(),
+    ///     url::Url::parse("https://example.org").unwrap(),
+    /// ];
+    /// let outcomes = rule.dispatch_all(&urls);
+    /// let pids: Vec<_> = outcomes.into_iter().map(|outcome| outcome.unwrap().pid).collect();
+    /// assert_eq!(pids[0], pids[1]);
+    /// ```
+    pub fn dispatch_all(&self, urls: &[Url]) -> Vec<Result<Dispatched, Rc<DispatchError>>> {
+        let every = |outcome: Result<Dispatched, Rc<DispatchError>>| -> Vec<_> {
+            urls.iter()
+                .map(|url| outcome.clone().map(|dispatched| Dispatched { url: url.clone(), ..dispatched }))
+                .collect()
+        };
+        let first = match urls.first() {
+            Some(first) => first,
+            None => return Vec::new(),
+        };
+        let candidates: Vec<_> = match self.chooser {
+            Some(ref menu) => match self.choose(menu, first) {
+                Ok(launcher) => vec![Candidate::new(launcher)],
+                Err(error) => return every(Err(Rc::new(error))),
+            },
+            None => self.launchers().map(Candidate::new).collect(),
+        };
+
+        let mut failures = Vec::new();
+        for (n, candidate) in candidates.iter().enumerate() {
+            let started = match candidate.launcher.argv_all(urls, candidate.remote()) {
+                Ok(Some(argv)) => self.start(candidate.launcher, argv, first),
+                // One URL at a time from here on, each with its own fallbacks;
+                // URLs the launcher cannot open in a batch fail on their own
+                Ok(None) | Err(_) => {
+                    return urls
+                        .iter()
+                        .map(|url| self.dispatch_from(&candidates[n..], failures.clone(), url).map_err(Rc::new))
+                        .collect()
+                }
+            };
+            match started {
+                Ok(dispatched) => return every(Ok(Dispatched { failures, ..dispatched })),
+                Err(DispatchError::Launch(error @ ::Error::NotFound(_))) => failures.push(Attempt {
+                    launcher: candidate.launcher.name().to_owned(),
+                    error,
+                }),
+                Err(error) => return every(Err(Rc::new(error))),
+            }
+        }
+        every(Err(Rc::new(exhausted(failures))))
+    }
+
+    /// Check if the rules are the same, including default rules of the same handlers.
+    fn same(&self, other: &Rule) -> bool {
+        self.name == other.name
+            && self.launchers().map(Launcher::name).eq(other.launchers().map(Launcher::name))
+    }
+}
+
+/// URLs to be opened with the same rule.
+#[derive(Debug, Clone)]
+pub struct Batch<'r> {
+    /// The selected rule.
+    pub rule: Cow<'r, Rule>,
+    /// The URLs, in order.
+    pub urls: Vec<Url>,
+    /// Positions of the URLs among all the grouped ones.
+    pub positions: Vec<usize>,
+}
+
+impl<'r> Batch<'r> {
+    /// Group the URLs, each with its position and selected rule, by the rule;
+    /// the batches are ordered by their first URL.
+    pub fn group<I>(selected: I) -> Vec<Batch<'r>>
+    where
+        I: IntoIterator<Item = (usize, Url, Cow<'r, Rule>)>,
+    {
+        let mut batches: Vec<Batch<'r>> = Vec::new();
+        for (position, url, rule) in selected {
+            match batches.iter_mut().find(|batch| batch.rule.same(&rule)) {
+                Some(batch) => {
+                    batch.urls.push(url);
+                    batch.positions.push(position);
+                }
+                None => batches.push(Batch {
+                    rule,
+                    urls: vec![url],
+                    positions: vec![position],
+                }),
+            }
+        }
+        batches
+    }
+
+    /// Open the URLs with the rule; returns the outcome for each URL, in order.
+    pub fn dispatch(&self) -> Vec<Result<Dispatched, Rc<DispatchError>>> {
+        self.rule.dispatch_all(&self.urls)
+    }
+}
+
+/// Ordered collection of rules, along with rewrites of the dispatched URLs
+/// and default handlers for URLs not matched by any rule.
+#[derive(Debug, Clone, Default)]
+pub struct Dispatcher {
+    rules: Vec<Rule>,
+    rewriter: Rewriter,
+    mime_apps: Option<MimeApps>,
+}
+
+impl Dispatcher {
+    /// Create a dispatcher from rules, in order of preference.
+    pub fn new(rules: Vec<Rule>) -> Dispatcher {
+        Dispatcher {
+            rules,
+            rewriter: Rewriter::default(),
+            mime_apps: None,
+        }
+    }
+
+    /// Rewrite URLs before the launcher is selected.
+    pub fn with_rewriter(mut self, rewriter: Rewriter) -> Dispatcher {
+        self.rewriter = rewriter;
+        self
+    }
+
+    /// Open URLs not matched by any rule with the default handler of their scheme.
+    pub fn with_mime_apps(mut self, mime_apps: MimeApps) -> Dispatcher {
+        self.mime_apps = Some(mime_apps);
+        self
+    }
+
+    /// Rewrites of the dispatched URLs.
+    pub fn rewriter(&self) -> &Rewriter {
+        &self.rewriter
+    }
+
+    /// The rules, in order of preference.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Find a rule by its name.
+    pub fn rule(&self, name: &str) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.name() == name)
+    }
+
+    /// Select the first rule matching the URL.
+    pub fn select(&self, url: &Url) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.matches(url))
+    }
+
+    /// Rule for URLs not matched by any rule, if default handlers are used:
+    /// the handlers of the `x-scheme-handler/<scheme>` MIME type, in order of preference,
+    /// with the less preferred ones as fallbacks.
+    /// Local files use the handlers of their MIME type and its supertypes instead.
+    pub fn default_rule(&self, url: &Url) -> Option<Rule> {
+        let apps = self.mime_apps.as_ref()?;
+        let mut conditions = Conditions::default().with_scheme(url.scheme());
+        let mut handlers = Vec::new();
+        if url.scheme() == "file" {
+            let types = mime::url_types(url);
+            for id in types.iter().flat_map(|mime| apps.handlers(mime)) {
+                if !handlers.contains(&id) {
+                    handlers.push(id);
+                }
+            }
+            if let Some(pattern) = types.first().and_then(|mime| mime.parse().ok()) {
+                conditions = conditions.with_mime(pattern);
+            }
+        } else {
+            handlers = apps.scheme_handlers(url.scheme());
+        }
+
+        // Once installed as the default handler, urldispatch would start itself
+        let mut handlers = handlers.into_iter().filter(|id| id != install::DESKTOP_ID);
+        let desktop = |id: String| Launcher::desktop(id.as_str(), id.as_str());
+        let rule = Rule::new("mimeapps", desktop(handlers.next()?));
+        let rule = handlers.fold(rule, |rule, id| rule.with_fallback(desktop(id)));
+
+        Some(rule.with_conditions(conditions))
+    }
+
+    /// Rewrite the URL and select the rule for it, configured or default one.
+    fn resolve(&self, url: &Url) -> Result<(Url, Cow<'_, Rule>), DispatchError> {
+        let url = self.rewriter.rewrite(url)?.url;
+        let rule = match self.select(&url) {
+            Some(rule) => Cow::Borrowed(rule),
+            None => Cow::Owned(
+                self.default_rule(&url)
+                    .ok_or_else(|| DispatchError::NoMatch(url.as_str().to_owned()))?,
+            ),
+        };
+        Ok((url, rule))
+    }
+
+    /// Rewrite the URL and prepare the launcher command for it.
+    pub fn command(&self, url: &Url) -> Result<process::Command, DispatchError> {
+        let (url, rule) = self.resolve(url)?;
+        Ok(rule.launcher().command(&url)?)
+    }
+
+    /// Rewrite the URL and open it with the selected launcher.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate urldispatch;
+    /// use urldispatch::launcher::Launcher;
+    /// use urldispatch::rules::{Dispatcher, Rule};
+    /// let launcher = Launcher::new("true", "true %u".parse().unwrap());
+    /// let dispatcher = Dispatcher::new(vec![Rule::new("web", launcher).with_scheme("https")]);
+    /// let url = url::Url::parse("https://example.com").unwrap();
+    /// dispatcher.dispatch(&url).expect("Failed to dispatch!");
+    /// ```
+    pub fn dispatch(&self, url: &Url) -> Result<(), DispatchError> {
+        self.dispatch_with_info(url).map(drop)
+    }
+
+    /// Rewrite the URL and open it with the selected launcher, describing what was started.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate urldispatch;
+    /// use urldispatch::launcher::Launcher;
+    /// use urldispatch::rules::{Dispatcher, Rule};
+    /// let launcher = Launcher::new("true", "true %u".parse().unwrap());
+    /// let dispatcher = Dispatcher::new(vec![Rule::new("web", launcher)]);
+    /// let url = url::Url::parse("https://example.com").unwrap();
+    /// let dispatched = dispatcher.dispatch_with_info(&url).expect("Failed to dispatch!");
+    /// assert_eq!(dispatched.rule, "web");
+    /// assert_eq!(dispatched.argv, ["true", "https://example.com/"]);
+    /// ```
+    pub fn dispatch_with_info(&self, url: &Url) -> Result<Dispatched, DispatchError> {
+        let (url, rule) = self.resolve(url)?;
+        rule.dispatch(&url)
+    }
+
+    /// Rewrite the URLs and open them, grouped by the selected rule
+    /// (see [`Rule::dispatch_all`](struct.Rule.html#method.dispatch_all));
+    /// returns the outcome for each URL, in order.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate urldispatch;
+    /// use urldispatch::launcher::Launcher;
+    /// use urldispatch::rules::{Dispatcher, Rule};
+    /// let launcher = Launcher::new("true", "true %U".parse().unwrap());
+    /// let dispatcher = Dispatcher::new(vec![Rule::new("web", launcher).with_scheme("https")]);
+    /// let urls = [
+    ///     url::Url::parse("https://example.com").unwrap(),
+    ///     url::Url::parse("gopher://example.com").unwrap(),
+    /// ];
+    /// let outcomes = dispatcher.dispatch_all(&urls);
+    /// assert!(outcomes[0].is_ok());
+    /// assert!(outcomes[1].is_err());
+    /// ```
+    pub fn dispatch_all(&self, urls: &[Url]) -> Vec<Result<Dispatched, Rc<DispatchError>>> {
+        let mut outcomes: Vec<_> = urls.iter().map(|_| None).collect();
+        let mut selected = Vec::new();
+        for (position, url) in urls.iter().enumerate() {
+            match self.resolve(url) {
+                Ok((url, rule)) => selected.push((position, url, rule)),
+                Err(error) => outcomes[position] = Some(Err(Rc::new(error))),
+            }
+        }
+        for batch in Batch::group(selected) {
+            for (&position, outcome) in batch.positions.iter().zip(batch.dispatch()) {
+                outcomes[position] = Some(outcome);
+            }
+        }
+        outcomes.into_iter().map(|outcome| outcome.expect("every URL dispatched")).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    fn url(input: &str) -> Url {
+        Url::parse(input).unwrap()
+    }
+
+    fn rule(name: &str) -> Rule {
+        Rule::new(name, Launcher::new("echo", "echo %u".parse().unwrap()))
+    }
+
+    #[test]
+    fn host_patterns() {
+        let exact: HostPattern = "Example.com".parse().unwrap();
+        assert!(exact.matches("example.com"));
+        assert!(!exact.matches("www.example.com"));
+
+        let suffix: HostPattern = ".example.com".parse().unwrap();
+        assert!(suffix.matches("example.com"));
+        assert!(suffix.matches("a.b.example.com"));
+        assert!(!suffix.matches("badexample.com"));
+
+        let glob: HostPattern = "*.example.?om".parse().unwrap();
+        assert!(glob.matches("a.b.example.com"));
+        assert!(!glob.matches("example.com"));
+        assert!(!glob.matches("a.exampleXcom"));
+
+        for pattern in &["example.com", ".example.com", "*.example.?om"] {
+            assert_eq!(pattern.parse::<HostPattern>().unwrap().to_string(), *pattern);
+        }
+    }
+
+    #[test]
+    fn rule_requires_all_conditions() {
+        let rule = rule("docs")
+            .with_scheme("HTTPS")
+            .with_host(".example.com".parse().unwrap())
+            .with_path("/docs/")
+            .with_regex(Regex::new(r"\.pdf$").unwrap());
+
+        assert!(rule.matches(&url("https://www.example.com/docs/a.pdf")));
+        assert!(!rule.matches(&url("http://www.example.com/docs/a.pdf")));
+        assert!(!rule.matches(&url("https://www.example.org/docs/a.pdf")));
+        assert!(!rule.matches(&url("https://www.example.com/blog/a.pdf")));
+        assert!(!rule.matches(&url("https://www.example.com/docs/a.html")));
+        assert!(!rule.matches(&url("mailto:docs@example.com")));
+    }
+
+    #[test]
+    fn rule_explains_conditions() {
+        let docs = rule("docs")
+            .with_scheme("https")
+            .with_host(".example.com".parse().unwrap())
+            .with_path("/docs/");
+        let explained: Vec<_> = docs
+            .explain(&url("https://example.com/blog/"))
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            explained,
+            [
+                "scheme is https: yes",
+                "host matches .example.com: yes",
+                "path starts with /docs/: no",
+            ]
+        );
+        assert!(rule("any").explain(&url("https://example.com")).is_empty());
+    }
+
+    #[test]
+    fn mime_condition_requires_local_file() {
+        let directories = rule("dirs").with_mime("inode/*".parse().unwrap());
+        let here = Url::from_directory_path(env::temp_dir()).unwrap();
+
+        assert!(directories.matches(&here));
+        assert!(!directories.matches(&here.join("urldispatch-missing-file").unwrap()));
+        assert!(!directories.matches(&url("https://example.com/")));
+        let explained: Vec<_> = directories.explain(&here).iter().map(ToString::to_string).collect();
+        assert_eq!(explained, ["MIME type matches inode/*: yes"]);
+    }
+
+    #[test]
+    fn dispatcher_selects_first_match() {
+        let dispatcher = Dispatcher::new(vec![
+            rule("mail").with_scheme("mailto"),
+            rule("example").with_host("example.com".parse().unwrap()),
+            rule("fallback"),
+        ]);
+        let selected = |u| dispatcher.select(&url(u)).map(Rule::name);
+
+        assert_eq!(selected("mailto:a@example.com"), Some("mail"));
+        assert_eq!(selected("https://example.com/"), Some("example"));
+        assert_eq!(selected("https://example.org/"), Some("fallback"));
+        assert!(Dispatcher::default().select(&url("https://example.com/")).is_none());
+    }
+
+    #[test]
+    fn dispatcher_rewrites_before_selection() {
+        use rewrite::{Action, Rewrite};
+
+        let nitter = Rewrite::new("nitter", Action::set_host("nitter.net").unwrap())
+            .with_conditions(Conditions::default().with_host("twitter.com".parse().unwrap()));
+        let dispatcher = Dispatcher::new(vec![
+            Rule::new("nitter", Launcher::new("true", "true %u".parse().unwrap()))
+                .with_host("nitter.net".parse().unwrap()),
+        ])
+        .with_rewriter(Rewriter::new(vec![nitter]));
+
+        let dispatched = dispatcher.dispatch_with_info(&url("https://twitter.com/a")).unwrap();
+        assert_eq!(dispatched.rule, "nitter");
+        assert_eq!(dispatched.url, url("https://nitter.net/a"));
+        assert_eq!(dispatched.argv, ["true", "https://nitter.net/a"]);
+    }
+
+    #[test]
+    fn dispatcher_falls_back_to_default_handlers() {
+        use mimeapps::Associations;
+
+        let apps = MimeApps::new(vec![Associations::parse(
+            "[Default Applications]\nx-scheme-handler/https=urldispatch.desktop;a.desktop;b.desktop\n",
+        )]);
+        let dispatcher = Dispatcher::new(vec![rule("mail").with_scheme("mailto")]).with_mime_apps(apps);
+
+        let https = url("https://example.com/");
+        assert!(dispatcher.select(&https).is_none());
+        let default = dispatcher.default_rule(&https).unwrap();
+        let names: Vec<_> = default.launchers().map(Launcher::name).collect();
+        assert_eq!(names, ["a.desktop", "b.desktop"]);
+        assert!(default.matches(&https));
+
+        assert!(dispatcher.default_rule(&url("gopher://example.com/")).is_none());
+        match dispatcher.dispatch(&url("gopher://example.com/")) {
+            Err(DispatchError::NoMatch(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rule_falls_back_to_found_launcher() {
+        let launcher = |name: &str, command: &str| Launcher::new(name, command.parse().unwrap());
+        let chain = Rule::new("chain", launcher("first", "asdfghjkl %u"))
+            .with_fallback(launcher("second", "qwertyuiop %u"))
+            .with_fallback(launcher("third", "true %u"));
+
+        let dispatched = chain.dispatch(&url("https://example.com")).unwrap();
+        assert_eq!(dispatched.launcher, "third");
+        assert_eq!(dispatched.argv, ["true", "https://example.com/"]);
+        let failed: Vec<_> = dispatched.failures.iter().map(|a| a.launcher.as_str()).collect();
+        assert_eq!(failed, ["first", "second"]);
+
+        let missing = Rule::new("missing", launcher("first", "asdfghjkl"))
+            .with_fallback(launcher("second", "qwertyuiop"));
+        match missing.dispatch(&url("https://example.com")) {
+            Err(DispatchError::Exhausted(ref attempts)) if attempts.len() == 2 => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match Rule::new("single", launcher("first", "asdfghjkl")).dispatch(&url("https://example.com")) {
+            Err(DispatchError::Launch(::Error::NotFound(_))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Other failures do not fall back
+        let denied = Rule::new("denied", launcher("first", "/")).with_fallback(launcher("second", "true"));
+        match denied.dispatch(&url("https://example.com")) {
+            Err(DispatchError::Launch(::Error::PermissionDenied(_))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dispatcher_batches_urls() {
+        let launcher = |name: &str, command: &str| Launcher::new(name, command.parse().unwrap());
+        let dispatcher = Dispatcher::new(vec![
+            Rule::new("multi", launcher("first", "asdfghjkl %U"))
+                .with_fallback(launcher("second", "true %U"))
+                .with_host("example.com".parse().unwrap()),
+            Rule::new("single", launcher("third", "true %u")).with_scheme("https"),
+        ]);
+        let urls: Vec<_> = ["https://example.com/a", "https://example.org/x", "https://example.com/b", "ftp://x/"]
+            .iter()
+            .map(|input| url(input))
+            .collect();
+
+        let outcomes = dispatcher.dispatch_all(&urls);
+        let dispatched: Vec<_> = outcomes[..3].iter().map(|outcome| outcome.as_ref().unwrap()).collect();
+        assert_eq!(dispatched[0].pid, dispatched[2].pid);
+        assert_eq!(dispatched[2].url, urls[2]);
+        assert_eq!(dispatched[0].argv, ["true", "https://example.com/a", "https://example.com/b"]);
+        assert_eq!(dispatched[0].failures[0].launcher, "first");
+        assert_eq!(dispatched[1].rule, "single");
+        assert_eq!(dispatched[1].argv, ["true", "https://example.org/x"]);
+        match **outcomes[3].as_ref().unwrap_err() {
+            DispatchError::NoMatch(ref url) => assert_eq!(url, "ftp://x/"),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+
+        // Launchers taking a single URL are started for each of them
+        let single = Rule::new("single", launcher("third", "true %u"));
+        let pids: Vec<_> = single.dispatch_all(&urls[..2]).into_iter().map(|o| o.unwrap().pid).collect();
+        assert_ne!(pids[0], pids[1]);
+        assert!(single.dispatch_all(&[]).is_empty());
+
+        // URLs that cannot be opened together are opened one by one
+        let files = Rule::new("files", launcher("fourth", "true %F"));
+        let mixed = [url("file:///tmp/a"), url("https://example.com/"), url("file:///tmp/b")];
+        let outcomes = files.dispatch_all(&mixed);
+        assert_eq!(outcomes[0].as_ref().unwrap().argv, ["true", "/tmp/a"]);
+        assert_eq!(outcomes[2].as_ref().unwrap().argv, ["true", "/tmp/b"]);
+        match **outcomes[1].as_ref().unwrap_err() {
+            DispatchError::Template(TemplateError::NotLocalFile(ref url)) => assert_eq!(url, "https://example.com/"),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rule_dispatches_chosen_launcher() {
+        use std::{env, fs, process};
+
+        let launcher = |name: &str| Launcher::new(name, "true %u".parse().unwrap());
+        let choices = env::temp_dir().join(format!("urldispatch-chooser-{}", process::id()));
+        // Stub menu: record the choices, pick the second one
+        let stub = |script: &str| {
+            let script = script.replace("CHOICES", &sh::quote(&choices.to_string_lossy()));
+            Rule::new("ask", launcher("first"))
+                .with_fallback(launcher("second"))
+                .with_chooser(format!("sh -c {}", sh::quote(&script)).parse().unwrap())
+        };
+
+        let dispatched = stub("tee CHOICES | sed -n 2p").dispatch(&url("https://example.com")).unwrap();
+        assert_eq!(dispatched.launcher, "second");
+        assert_eq!(dispatched.argv, ["true", "https://example.com/"]);
+        assert_eq!(fs::read_to_string(&choices).unwrap(), "first\nsecond\n");
+        fs::remove_file(&choices).unwrap();
+
+        match stub("cat >/dev/null; exit 1").dispatch(&url("https://example.com")) {
+            Err(DispatchError::Cancelled) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match stub("echo third").dispatch(&url("https://example.com")) {
+            Err(DispatchError::UnknownChoice(ref choice)) if choice == "third" => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
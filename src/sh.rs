@@ -3,75 +3,387 @@
 //! -   Command dispatching
 //! -   CLI lexing and expansions (`~`, `$VAR`)
 
-use std::cmp::Ordering;
-use std::{io, process};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::thread;
 
-use nix::sys::wait::{waitpid, WaitStatus};
-use nix::unistd::{fork, ForkResult, Pid};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{chdir, close, fork, pipe2, setsid, sysconf, ForkResult, Pid, SysconfVar};
 
-/// Communication between dispatch processes using exit codes.
-/// Possible exit codes and their meaning:
+use Error;
+
+mod expand;
+mod lex;
+mod spawn;
+
+pub use self::expand::{expand, ExpansionError};
+pub use self::lex::{lex, quote, split, Fragment, LexError, Quoting, Word};
+
+/// Destination of the standard output and error of a dispatched command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Output {
+    /// Share the streams with the caller.
+    #[default]
+    Inherit,
+    /// Discard everything.
+    Null,
+    /// Append everything to a file.
+    Log(PathBuf),
+}
+
+/// Degree of separation of the dispatched command from the caller.
 ///
-/// - `0`: Dispatch successful.
-/// - `> 0`: Error, code == errno.
-/// - `< 0`: Unknown/other error.
-#[derive(Debug)]
-struct ExitProtocol(i32);
+/// The default keeps the command in the caller's session,
+/// with the same working directory and standard streams.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Detach {
+    /// Start a new session, without controlling terminal.
+    pub new_session: bool,
+    /// Change working directory to `/`, so that no file system is kept busy.
+    pub chdir_root: bool,
+    /// Redirect the standard output and error; unless inherited,
+    /// standard input is redirected from `/dev/null`.
+    pub output: Output,
+    /// Close all inherited file descriptors except the standard streams.
+    pub close_fds: bool,
+}
 
-impl From<io::Error> for ExitProtocol {
-    fn from(error: io::Error) -> ExitProtocol {
-        match error.raw_os_error() {
-            Some(errno) => ExitProtocol(errno),
-            None => ExitProtocol(-1),
+impl Detach {
+    /// Detach the command completely, as a daemon.
+    pub fn daemon() -> Detach {
+        Detach {
+            new_session: true,
+            chdir_root: true,
+            output: Output::Null,
+            close_fds: true,
         }
     }
 }
-impl Into<i32> for ExitProtocol {
-    fn into(self) -> i32 {
-        self.0
+
+/// Mechanism used for starting the command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Fork a helper process, which detaches itself and starts the command.
+    ///
+    /// The helper runs arbitrary code after `fork`,
+    /// which is not safe if the caller has multiple threads.
+    #[default]
+    Fork,
+    /// Start the command directly with `posix_spawn`.
+    ///
+    /// Safe in multithreaded programs, but only the program, arguments,
+    /// environment changes and working directory of the command are honoured.
+    /// The command is a child of the caller, which is responsible for reaping it.
+    ///
+    /// Without glibc, commands changing the working directory or closing
+    /// the inherited descriptors are started with the fork backend.
+    Spawn,
+}
+
+/// Stage of the dispatch at which the helper failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// Detaching the helper from the caller.
+    Detach = 0,
+    /// Executing the command.
+    Exec = 1,
+}
+
+/// Failure report, sent from the dispatch helper to its parent.
+///
+/// On the wire, the report is the stage, native-endian `errno`
+/// and the subject of the failed operation (program name or similar).
+#[derive(Debug, PartialEq, Eq)]
+struct Report {
+    stage: Stage,
+    errno: i32,
+    subject: OsString,
+}
+
+impl Report {
+    fn new<S: AsRef<OsStr>>(stage: Stage, error: &io::Error, subject: S) -> Report {
+        Report {
+            stage,
+            errno: error.raw_os_error().unwrap_or(0),
+            subject: subject.as_ref().to_owned(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![self.stage as u8];
+        bytes.extend_from_slice(&self.errno.to_ne_bytes());
+        bytes.extend_from_slice(self.subject.as_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Report> {
+        if bytes.len() < 5 {
+            return None;
+        }
+        let stage = match bytes[0] {
+            0 => Stage::Detach,
+            1 => Stage::Exec,
+            _ => return None,
+        };
+        Some(Report {
+            stage,
+            errno: i32::from_ne_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
+            subject: OsString::from_vec(bytes[5..].to_vec()),
+        })
     }
 }
-impl Into<::nix::Result<()>> for ExitProtocol {
-    fn into(self) -> ::nix::Result<()> {
-        use nix::{Error, errno::{Errno, from_i32}};
 
-        match self.0.cmp(&0) {
-            Ordering::Less => Err(Error::Sys(Errno::UnknownErrno)),
-            Ordering::Equal => Ok(()),
-            Ordering::Greater => Err(Error::Sys(from_i32(self.0))),
+/// Tag of the [`Message::Started`] on the wire; distinct from all stages.
+const STARTED: u8 = 2;
+
+/// Message sent from the dispatch helper to its parent.
+///
+/// The helper and its parent communicate through a close-on-exec pipe:
+/// the helper writes exactly one message, then exits.
+#[derive(Debug, PartialEq, Eq)]
+enum Message {
+    /// The command was started with this PID.
+    Started(Pid),
+    /// The command could not be started.
+    Failed(Report),
+}
+
+impl Message {
+    fn encode(&self) -> Vec<u8> {
+        match *self {
+            Message::Started(pid) => {
+                let mut bytes = vec![STARTED];
+                bytes.extend_from_slice(&i32::from(pid).to_ne_bytes());
+                bytes
+            }
+            Message::Failed(ref report) => report.encode(),
         }
     }
+
+    fn decode(bytes: &[u8]) -> Option<Message> {
+        match bytes.first() {
+            Some(&STARTED) if bytes.len() == 5 => {
+                let pid = i32::from_ne_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+                Some(Message::Started(Pid::from_raw(pid)))
+            }
+            Some(&STARTED) => None,
+            _ => Report::decode(bytes).map(Message::Failed),
+        }
+    }
+}
+
+impl From<Report> for Error {
+    fn from(report: Report) -> Error {
+        let errno = Errno::from_i32(report.errno);
+        match report.stage {
+            Stage::Detach => Error::Detach(report.subject, errno),
+            Stage::Exec => Error::exec(report.subject, errno),
+        }
+    }
+}
+
+/// Close all file descriptors above the standard streams, except `keep`.
+fn close_fds(keep: RawFd) {
+    let open: Vec<RawFd> = match fs::read_dir("/proc/self/fd") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(_) => {
+            let max = sysconf(SysconfVar::OPEN_MAX).ok().and_then(|max| max).unwrap_or(1024);
+            (0..max as RawFd).collect()
+        }
+    };
+
+    for fd in open.into_iter().filter(|&fd| fd > 2 && fd != keep) {
+        // The descriptor list may be stale, so failures are expected
+        let _ = close(fd);
+    }
+}
+
+/// Detach the helper (and thus the command) from the caller.
+fn detach(command: &mut process::Command, options: &Detach, status: RawFd) -> Result<(), Report> {
+    let failed = |subject: &str, err: ::nix::Error| {
+        let errno = match err {
+            ::nix::Error::Sys(errno) => errno as i32,
+            _ => 0,
+        };
+        Report::new(Stage::Detach, &io::Error::from_raw_os_error(errno), subject)
+    };
+
+    if options.close_fds {
+        close_fds(status);
+    }
+    if options.new_session {
+        setsid().map_err(|err| failed("setsid", err))?;
+    }
+    if options.chdir_root {
+        chdir("/").map_err(|err| failed("chdir", err))?;
+    }
+
+    let log = |path: &Path| {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| Report::new(Stage::Detach, &err, path))
+    };
+    match options.output {
+        Output::Inherit => {}
+        Output::Null => {
+            command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+        }
+        Output::Log(ref path) => {
+            command.stdin(Stdio::null()).stdout(log(path)?).stderr(log(path)?);
+        }
+    }
+
+    Ok(())
 }
 
 /// Dispatch in a child process.
 ///
-/// Spawns the passed command and use exit code to indicate status.
-fn dispatch_child(mut command: process::Command) -> ! {
-    match command.spawn() {
-        Ok(_) => process::exit(ExitProtocol(0).into()),
-        Err(error) => process::exit(ExitProtocol::from(error).into()),
-    }
+/// Detaches itself from the caller, spawns the passed command
+/// and reports the outcome through the status pipe.
+fn dispatch_child(mut command: process::Command, options: &Detach, status: RawFd) -> ! {
+    let mut status_pipe = unsafe { File::from_raw_fd(status) };
+
+    let result = detach(&mut command, options, status).and_then(|_| {
+        command
+            .spawn()
+            .map_err(|err| Report::new(Stage::Exec, &err, command.get_program()))
+    });
+    let (message, code) = match result {
+        Ok(child) => (Message::Started(Pid::from_raw(child.id() as i32)), 0),
+        Err(report) => (Message::Failed(report), 1),
+    };
+    // Nobody to tell if this fails; the parent will see the exit code
+    let _ = status_pipe.write_all(&message.encode());
+    process::exit(code)
 }
 
-/// Dispatch in the parent process.
+/// Dispatch in progress.
 ///
-/// Waits for the child to return its exit code and turn it into result.
-fn dispatch_parent(child: Pid) -> ::nix::Result<()> {
-    use nix::{Error, errno::Errno};
+/// The helper process reports the outcome through a close-on-exec pipe,
+/// which becomes readable once the outcome is known;
+/// its descriptor can thus be registered in an event loop.
+/// Dropping the handle before the outcome is known does not block;
+/// a helper still running is then reaped by a background thread.
+#[derive(Debug)]
+pub struct Pending {
+    helper: Pid,
+    status: File,
+    message: Vec<u8>,
+    outcome: Option<Result<Pid, Error>>,
+}
 
-    const INTERRUPTED: ::nix::Result<()> = Err(Error::Sys(Errno::EINTR));
+impl Pending {
+    /// Read the available part of the message; returns `true` at EOF.
+    fn read(&mut self) -> io::Result<bool> {
+        let mut buffer = [0; 256];
+        loop {
+            match self.status.read(&mut buffer) {
+                Ok(0) => return Ok(true),
+                Ok(read) => self.message.extend_from_slice(&buffer[..read]),
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+    }
 
-    loop {
-        match waitpid(child, None)? {
-            WaitStatus::Exited(_, ec) => break ExitProtocol(ec).into(),
-            WaitStatus::Signaled(..) => break INTERRUPTED,
-            _ => continue,
+    /// Wait for the helper to exit; returns its exit code.
+    fn reap(&self) -> Result<i32, Error> {
+        loop {
+            match waitpid(self.helper, None) {
+                Ok(WaitStatus::Exited(_, code)) => return Ok(code),
+                Ok(WaitStatus::Signaled(_, signal, _)) => return Err(Error::Signaled(signal)),
+                Ok(_) | Err(::nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Reap the helper and turn its message into the outcome.
+    ///
+    /// The helper closes the pipe by exiting, so this does not block for long.
+    fn finish(&mut self, read: io::Result<bool>) -> Result<Pid, Error> {
+        let outcome = self.reap().and_then(|exit| {
+            if let Err(err) = read {
+                return Err(err.into());
+            }
+            match Message::decode(&self.message) {
+                Some(Message::Started(pid)) if exit == 0 => Ok(pid),
+                Some(Message::Failed(report)) => Err(report.into()),
+                _ => Err(Error::Unknown),
+            }
+        });
+        self.outcome = Some(outcome.clone());
+        outcome
+    }
+
+    /// Check the outcome without blocking.
+    ///
+    /// Returns `Ok(None)` if the outcome is not known yet,
+    /// otherwise the same as [`wait`](#method.wait).
+    pub fn try_wait(&mut self) -> Result<Option<Pid>, Error> {
+        if let Some(ref outcome) = self.outcome {
+            return outcome.clone().map(Some);
+        }
+        match self.read() {
+            Ok(false) => Ok(None),
+            read => self.finish(read).map(Some),
+        }
+    }
+
+    /// Block until the outcome is known; returns PID of the started command.
+    pub fn wait(mut self) -> Result<Pid, Error> {
+        if let Some(ref outcome) = self.outcome {
+            return outcome.clone();
+        }
+        let read = match fcntl(self.status.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty())) {
+            Ok(_) => self.read(),
+            Err(::nix::Error::Sys(errno)) => Err(io::Error::from_raw_os_error(errno as i32)),
+            Err(err) => Err(io::Error::other(err)),
+        };
+        self.finish(read)
+    }
+}
+
+impl AsRawFd for Pending {
+    /// Status pipe; readable when the outcome is known.
+    fn as_raw_fd(&self) -> RawFd {
+        self.status.as_raw_fd()
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if self.outcome.is_some() {
+            return;
+        }
+        // Do not leave zombies behind, nor wait for the helper still running
+        let helper = self.helper;
+        if let Ok(WaitStatus::StillAlive) = waitpid(helper, Some(WaitPidFlag::WNOHANG)) {
+            let reaper = thread::Builder::new()
+                .name("urldispatch-reaper".into())
+                .spawn(move || while let Err(::nix::Error::Sys(Errno::EINTR)) = waitpid(helper, None) {});
+            drop(reaper);
         }
     }
 }
 
 /// Starts and detaches a command.
 ///
+/// The command is started by a short-lived helper process,
+/// so that the caller does not need to reap it.
+///
 /// # Examples
 ///
 /// Basic usage:
@@ -81,13 +393,160 @@ fn dispatch_parent(child: Pid) -> ::nix::Result<()> {
 /// let mut command = std::process::Command::new("true");
 /// dispatch(command).expect("Failed to execute!");
 /// ```
-pub fn dispatch(command: process::Command) -> ::nix::Result<()> {
-    match fork()? {
-        ForkResult::Child => dispatch_child(command),
-        ForkResult::Parent { child, .. } => dispatch_parent(child),
+pub fn dispatch(command: process::Command) -> Result<(), Error> {
+    dispatch_with(command, &Detach::default()).map(drop)
+}
+
+/// Starts a command, detached from the caller as specified;
+/// returns PID of the started command.
+///
+/// The command is not a child of the caller, so the PID can only be used
+/// for logging or signalling, not for waiting on the command.
+///
+/// As the helper process starting the command is not a process group leader,
+/// it can start a new session; the command itself then cannot
+/// acquire a controlling terminal by accident.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::sh::{dispatch_with, Detach};
+/// let mut command = std::process::Command::new("true");
+/// let pid = dispatch_with(command, &Detach::daemon()).expect("Failed to execute!");
+/// println!("started as {}", pid);
+/// ```
+pub fn dispatch_with(command: process::Command, options: &Detach) -> Result<Pid, Error> {
+    dispatch_nonblocking(command, options)?.wait()
+}
+
+/// Starts a command like [`dispatch_with`](fn.dispatch_with.html),
+/// without waiting for the outcome.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use std::os::unix::io::AsRawFd;
+/// use urldispatch::sh::{dispatch_nonblocking, Detach};
+/// let mut command = std::process::Command::new("true");
+/// let mut pending = dispatch_nonblocking(command, &Detach::default()).expect("Failed to fork!");
+/// println!("register {} in the event loop", pending.as_raw_fd());
+/// while pending.try_wait().expect("Failed to execute!").is_none() {
+///     std::thread::yield_now();
+/// }
+/// ```
+pub fn dispatch_nonblocking(command: process::Command, options: &Detach) -> Result<Pending, Error> {
+    let (read, write) = pipe2(OFlag::O_CLOEXEC)?;
+
+    match fork() {
+        Ok(ForkResult::Child) => {
+            let _ = close(read);
+            dispatch_child(command, options, write)
+        }
+        Ok(ForkResult::Parent { child, .. }) => {
+            let _ = close(write);
+            // Created first, so that the helper is reaped even on failure below
+            let pending = Pending {
+                helper: child,
+                status: unsafe { File::from_raw_fd(read) },
+                message: Vec::new(),
+                outcome: None,
+            };
+            fcntl(read, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+            Ok(pending)
+        }
+        Err(err) => {
+            let _ = close(read);
+            let _ = close(write);
+            Err(Error::Fork(err))
+        }
+    }
+}
+
+/// Starts a command like [`dispatch_with`](fn.dispatch_with.html),
+/// using the selected backend.
+///
+/// Commands started by [`Backend::Spawn`](enum.Backend.html#variant.Spawn)
+/// are children of the caller and need to be reaped by it.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::sh::{dispatch_using, Backend, Detach};
+/// let mut command = std::process::Command::new("true");
+/// dispatch_using(command, &Detach::daemon(), Backend::Spawn).expect("Failed to execute!");
+/// ```
+pub fn dispatch_using(command: process::Command, options: &Detach, backend: Backend) -> Result<Pid, Error> {
+    match backend {
+        Backend::Fork => dispatch_with(command, options),
+        Backend::Spawn if spawn::supports(&command, options) => spawn::spawn(&command, options),
+        Backend::Spawn => dispatch_with(command, options),
     }
 }
 
+/// Runs a command to completion, feeding it the input, and captures its output.
+///
+/// Unlike [`dispatch`](fn.dispatch.html), this waits for the command.
+/// Standard error is inherited, so that interactive programs
+/// such as menus can report problems to the user.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::sh::capture;
+/// let mut command = std::process::Command::new("sort");
+/// let output = capture(command, b"b\na\n").expect("Failed to execute!");
+/// assert_eq!(output.stdout, b"a\nb\n");
+/// ```
+pub fn capture(mut command: process::Command, input: &[u8]) -> Result<process::Output, Error> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| Error::exec(command.get_program(), Errno::from_i32(err.raw_os_error().unwrap_or(0))))?;
+
+    // Written concurrently, so that neither side blocks on a full pipe
+    let mut stdin = child.stdin.take().ok_or(Error::Unknown)?;
+    let input = input.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output()?;
+    // The command may exit without reading everything; that is its choice
+    let _ = writer.join();
+
+    Ok(output)
+}
+
+/// Prepares a command from an argument vector.
+///
+/// Returns `None` if there is no program to execute.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::sh::{command, dispatch, expand, split};
+/// let argv = expand(&split("true ~/ignored '$arguments'").unwrap()).unwrap();
+/// dispatch(command(&argv).unwrap()).expect("Failed to execute!");
+/// ```
+pub fn command<I, S>(argv: I) -> Option<process::Command>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut argv = argv.into_iter();
+    let mut command = process::Command::new(argv.next()?);
+    command.args(argv);
+    Some(command)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -98,4 +557,254 @@ mod test {
     fn dispatch_reports_failure() {
         dispatch(process::Command::new("asdfghjkl")).unwrap()
     }
+
+    const BACKENDS: [Backend; 2] = [Backend::Fork, Backend::Spawn];
+
+    #[test]
+    fn dispatch_distinguishes_failures() {
+        for &backend in &BACKENDS {
+            let dispatch = |program| dispatch_using(process::Command::new(program), &Detach::default(), backend);
+            match dispatch("asdfghjkl") {
+                Err(Error::NotFound(ref program)) if program == "asdfghjkl" => {}
+                other => panic!("unexpected result with {:?}: {:?}", backend, other),
+            }
+            match dispatch("/") {
+                Err(Error::PermissionDenied(ref program)) if program == "/" => {}
+                other => panic!("unexpected result with {:?}: {:?}", backend, other),
+            }
+        }
+    }
+
+    #[test]
+    fn dispatch_does_not_wait_for_command() {
+        use std::time::{Duration, Instant};
+
+        let start = Instant::now();
+        let mut command = process::Command::new("sleep");
+        command.arg("5");
+        dispatch(command).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn dispatch_reports_pid() {
+        use nix::sys::signal::{kill, Signal};
+
+        let mut command = process::Command::new("sleep");
+        command.arg("5");
+        let pid = dispatch_with(command, &Detach::default()).unwrap();
+
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+        assert!(stat.starts_with(&format!("{} (sleep) ", pid)), "{}", stat);
+        kill(pid, Signal::SIGTERM).unwrap();
+    }
+
+    #[test]
+    fn dispatch_nonblocking_polls_outcome() {
+        use std::thread::sleep;
+        use std::time::Duration;
+
+        let mut pending = dispatch_nonblocking(process::Command::new("asdfghjkl"), &Detach::default()).unwrap();
+        assert!(pending.as_raw_fd() > 2);
+        let outcome = loop {
+            match pending.try_wait() {
+                Ok(None) => sleep(Duration::from_millis(10)),
+                outcome => break outcome,
+            }
+        };
+        match (outcome, pending.try_wait()) {
+            (Err(Error::NotFound(_)), Err(Error::NotFound(_))) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let pending = dispatch_nonblocking(process::Command::new("true"), &Detach::default()).unwrap();
+        assert!(pending.wait().is_ok());
+
+        // Dropped handles do not wait, but the helper is reaped anyway
+        let pending = dispatch_nonblocking(process::Command::new("true"), &Detach::default()).unwrap();
+        let helper = Path::new("/proc").join(pending.helper.to_string());
+        drop(pending);
+        assert!((0..100).any(|_| {
+            sleep(Duration::from_millis(10));
+            !helper.exists()
+        }));
+    }
+
+    #[test]
+    fn dispatch_detaches_command() {
+        use std::env;
+        use std::thread::sleep;
+        use std::time::Duration;
+
+        for &backend in &BACKENDS {
+            let log = env::temp_dir().join(format!("urldispatch-detach-{:?}-{}.log", backend, process::id()));
+            let _ = fs::remove_file(&log);
+            let mut command = process::Command::new("sh");
+            command.args(["-c", "pwd; cut -d' ' -f6 /proc/$$/stat; ls /proc/self/fd | wc -l"]);
+
+            let options = Detach {
+                output: Output::Log(log.clone()),
+                ..Detach::daemon()
+            };
+            dispatch_using(command, &options, backend).unwrap();
+            sleep(Duration::from_millis(500));
+
+            let output = fs::read_to_string(&log).unwrap();
+            let lines: Vec<_> = output.lines().map(str::trim).collect();
+            fs::remove_file(&log).unwrap();
+
+            assert_eq!(lines[0], "/", "{:?}", backend);
+            assert_ne!(lines[1], unsafe { ::nix::libc::getsid(0) }.to_string(), "{:?}", backend);
+            // Standard streams plus the one opened by ls
+            assert_eq!(lines[2], "4", "{:?}", backend);
+        }
+    }
+
+    #[test]
+    fn dispatch_reports_detach_failure() {
+        let options = Detach {
+            output: Output::Log("/nonexistent/directory/log".into()),
+            ..Detach::default()
+        };
+        for &backend in &BACKENDS {
+            match dispatch_using(process::Command::new("true"), &options, backend) {
+                Err(Error::Detach(ref subject, Errno::ENOENT)) if subject == "/nonexistent/directory/log" => {}
+                other => panic!("unexpected result with {:?}: {:?}", backend, other),
+            }
+        }
+    }
+
+    #[test]
+    fn spawn_backend_is_thread_safe() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::thread;
+
+        // Keep the allocator busy, so that a forked child would likely
+        // inherit its lock held and deadlock on the first allocation
+        let done = Arc::new(AtomicBool::new(false));
+        let allocator = {
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    let garbage: Vec<Vec<u8>> = (0..64).map(|size| vec![0; size * 64]).collect();
+                    drop(garbage);
+                }
+            })
+        };
+
+        let dispatchers: Vec<_> = (0..8)
+            .map(|n| {
+                thread::spawn(move || {
+                    let program = if n % 2 == 0 { "true" } else { "asdfghjkl" };
+                    for _ in 0..16 {
+                        let mut command = process::Command::new(program);
+                        command.env("URLDISPATCH_TEST_THREAD", n.to_string());
+                        match dispatch_using(command, &Detach::daemon(), Backend::Spawn) {
+                            Ok(pid) if program == "true" => {
+                                waitpid(pid, None).unwrap();
+                            }
+                            Err(Error::NotFound(_)) if program == "asdfghjkl" => {}
+                            other => panic!("unexpected result: {:?}", other),
+                        }
+                    }
+                })
+            })
+            .collect();
+        for dispatcher in dispatchers {
+            dispatcher.join().unwrap();
+        }
+
+        done.store(true, Ordering::Relaxed);
+        allocator.join().unwrap();
+    }
+
+    #[test]
+    fn spawn_backend_passes_environment() {
+        use std::env;
+
+        let log = env::temp_dir().join(format!("urldispatch-spawn-env-{}.log", process::id()));
+        let _ = fs::remove_file(&log);
+        let mut command = process::Command::new("sh");
+        command
+            .args(["-c", "echo \"$URLDISPATCH_TEST_SPAWN:${HOME:-unset}\"; pwd"])
+            .env("URLDISPATCH_TEST_SPAWN", "set")
+            .env_remove("HOME")
+            .current_dir("/tmp");
+
+        let options = Detach {
+            output: Output::Log(log.clone()),
+            ..Detach::default()
+        };
+        // The command is a child of the caller
+        let pid = dispatch_using(command, &options, Backend::Spawn).unwrap();
+        assert_eq!(waitpid(pid, None).unwrap(), WaitStatus::Exited(pid, 0));
+
+        let output = fs::read_to_string(&log).unwrap();
+        fs::remove_file(&log).unwrap();
+        assert_eq!(output, "set:unset\n/tmp\n");
+    }
+
+    #[test]
+    fn dispatch_searches_command_path() {
+        use std::env;
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = env::temp_dir().join(format!("urldispatch-path-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let program = directory.join("urldispatch-test");
+        fs::write(&program, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // The program is only in the command's PATH, not the caller's
+        for &backend in &BACKENDS {
+            let mut command = process::Command::new("urldispatch-test");
+            command.env("PATH", &directory);
+            match dispatch_using(command, &Detach::default(), backend) {
+                Ok(pid) if backend == Backend::Spawn => assert!(waitpid(pid, None).is_ok()),
+                Ok(_) => {}
+                Err(err) => panic!("unexpected result with {:?}: {:?}", backend, err),
+            }
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn report_roundtrips() {
+        let report = Report {
+            stage: Stage::Exec,
+            errno: Errno::ENOEXEC as i32,
+            subject: OsString::from("/usr/bin/\u{fc}ber"),
+        };
+        assert_eq!(Report::decode(&report.encode()), Some(report));
+        assert_eq!(Report::decode(&[1, 2]), None);
+        assert_eq!(Report::decode(&[7, 0, 0, 0, 0]), None);
+
+        let started = Message::Started(Pid::from_raw(4242));
+        assert_eq!(Message::decode(&started.encode()), Some(started));
+        let failed = Message::Failed(Report::new(Stage::Detach, &io::Error::from_raw_os_error(1), "setsid"));
+        assert_eq!(Message::decode(&failed.encode()), Some(failed));
+        assert_eq!(Message::decode(&[STARTED, 0, 0]), None);
+        assert_eq!(Message::decode(&[]), None);
+    }
+
+    #[test]
+    fn capture_feeds_input() {
+        let mut command = process::Command::new("tr");
+        command.args(["a-z", "A-Z"]);
+        let output = capture(command, b"abc").unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"ABC");
+
+        match capture(process::Command::new("asdfghjkl"), b"") {
+            Err(Error::NotFound(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn command_requires_program() {
+        assert!(command(Vec::<String>::new()).is_none());
+        assert!(command(["true"]).is_some());
+    }
 }
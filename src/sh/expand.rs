@@ -0,0 +1,399 @@
+//! Shell-like expansions of lexed words.
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::{env, error, fmt, mem, ptr};
+
+use nix::libc;
+
+use super::lex::{Fragment, Quoting, Word};
+
+/// Reasons for failed expansion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpansionError {
+    /// `${NAME:?message}` encountered unset or empty variable.
+    Unset { name: String, message: String },
+    /// Malformed or unsupported `${...}` expression.
+    BadSubstitution(String),
+}
+
+impl fmt::Display for ExpansionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExpansionError::Unset { ref name, ref message } if message.is_empty() => {
+                write!(f, "{}: parameter null or not set", name)
+            }
+            ExpansionError::Unset { ref name, ref message } => write!(f, "{}: {}", name, message),
+            ExpansionError::BadSubstitution(ref expr) => write!(f, "bad substitution: {}", expr),
+        }
+    }
+}
+
+impl error::Error for ExpansionError {}
+
+/// Upper bound for the passwd lookup buffer, to avoid growing it indefinitely.
+const PASSWD_BUFFER_LIMIT: usize = 1 << 20;
+
+/// Look up a home directory in the passwd database.
+///
+/// The `lookup` is expected to call one of the `getpw*_r` functions
+/// with the provided entry, buffer and result pointers.
+fn passwd_home<F>(lookup: F) -> Option<OsString>
+where
+    F: Fn(&mut libc::passwd, &mut [libc::c_char], &mut *mut libc::passwd) -> libc::c_int,
+{
+    let mut buffer = vec![0 as libc::c_char; 1024];
+
+    loop {
+        let mut entry: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+
+        match lookup(&mut entry, &mut buffer, &mut result) {
+            0 if result.is_null() => break None,
+            0 => {
+                let dir = unsafe { CStr::from_ptr(entry.pw_dir) };
+                break Some(OsStr::from_bytes(dir.to_bytes()).to_owned());
+            }
+            libc::ERANGE if buffer.len() < PASSWD_BUFFER_LIMIT => {
+                let len = buffer.len() * 2;
+                buffer.resize(len, 0);
+            }
+            _ => break None,
+        }
+    }
+}
+
+/// Home directory of the current user.
+///
+/// Prefers `$HOME`, falls back to the passwd database.
+fn own_home() -> Option<OsString> {
+    env::var_os("HOME").or_else(|| {
+        let uid = unsafe { libc::getuid() };
+        passwd_home(|entry, buffer, result| unsafe {
+            libc::getpwuid_r(uid, entry, buffer.as_mut_ptr(), buffer.len(), result)
+        })
+    })
+}
+
+/// Home directory of the named user.
+fn user_home(name: &str) -> Option<OsString> {
+    let name = CString::new(name).ok()?;
+    passwd_home(|entry, buffer, result| unsafe {
+        libc::getpwnam_r(name.as_ptr(), entry, buffer.as_mut_ptr(), buffer.len(), result)
+    })
+}
+
+/// Perform tilde expansion on a word.
+///
+/// The tilde prefix consists of an unquoted `~` at the start of the word
+/// and all characters up to the first `/` (or end of the word).
+/// If any of the characters is quoted, or the user is unknown,
+/// no expansion takes place and `None` is returned.
+fn tilde(word: &Word) -> Option<Word> {
+    let (first, rest) = word.fragments().split_first()?;
+    if first.quoting != Quoting::Unquoted || !first.text.starts_with('~') {
+        return None;
+    }
+
+    let (prefix, suffix) = match first.text.find('/') {
+        Some(slash) => first.text.split_at(slash),
+        None if rest.is_empty() => (first.text.as_str(), ""),
+        None => return None,
+    };
+    let home = match &prefix[1..] {
+        "" => own_home()?,
+        user => user_home(user)?,
+    };
+
+    let mut fragments = vec![Fragment {
+        text: home.to_string_lossy().into_owned(),
+        quoting: Quoting::Literal,
+    }];
+    if !suffix.is_empty() {
+        fragments.push(Fragment {
+            text: suffix.to_owned(),
+            quoting: Quoting::Unquoted,
+        });
+    }
+    fragments.extend_from_slice(rest);
+
+    Some(fragments.into())
+}
+
+/// Single character of a word, with its quoting.
+type QuotedChar = (char, Quoting);
+
+/// Append text to fragments, merging it with the last one if possible.
+fn append(fragments: &mut Vec<Fragment>, text: &str, quoting: Quoting) {
+    if text.is_empty() {
+        return;
+    }
+    match fragments.last_mut() {
+        Some(ref mut last) if last.quoting == quoting => last.text.push_str(text),
+        _ => fragments.push(Fragment {
+            text: text.to_owned(),
+            quoting,
+        }),
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c == '_' || c.is_ascii_alphabetic()
+}
+
+fn is_name(c: char) -> bool {
+    c == '_' || c.is_ascii_alphanumeric()
+}
+
+/// Length of the variable name at the start of input, in the given quoting.
+fn name_len(input: &[QuotedChar], quoting: Quoting) -> usize {
+    match input.first() {
+        Some(&(c, q)) if q == quoting && is_name_start(c) => input
+            .iter()
+            .take_while(|&&(c, q)| q == quoting && is_name(c))
+            .count(),
+        _ => 0,
+    }
+}
+
+/// Position of the `}` closing a `${` expression that starts the input.
+fn closing_brace(input: &[QuotedChar]) -> Option<usize> {
+    let mut depth = 0;
+    let mut chars = input.iter().enumerate().peekable();
+
+    while let Some((index, &(c, quoting))) = chars.next() {
+        if quoting == Quoting::Literal {
+            continue;
+        }
+        match c {
+            '$' if chars.peek().map(|&(_, &(c, _))| c) == Some('{') => {
+                chars.next();
+                depth += 1;
+            }
+            '}' if depth == 1 => return Some(index),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Current value of a variable; unset and non-unicode values are tolerated.
+fn variable(name: &str) -> Option<String> {
+    env::var_os(name).map(|value| value.to_string_lossy().into_owned())
+}
+
+/// Evaluate the inside of `${...}`.
+fn braced(body: &[QuotedChar], quoting: Quoting) -> Result<String, ExpansionError> {
+    let bad_substitution = || {
+        let expr: String = body.iter().map(|&(c, _)| c).collect();
+        ExpansionError::BadSubstitution(format!("${{{}}}", expr))
+    };
+
+    let len = name_len(body, quoting);
+    if len == 0 {
+        return Err(bad_substitution());
+    }
+    let name: String = body[..len].iter().map(|&(c, _)| c).collect();
+    let value = variable(&name).unwrap_or_default();
+
+    let operator: String = body[len..].iter().take(2).map(|&(c, _)| c).collect();
+    let word = || -> Result<String, ExpansionError> {
+        let fragments = parameters(&body[len + 2..])?;
+        Ok(fragments.iter().map(|f| f.text.as_str()).collect())
+    };
+    match operator.as_str() {
+        "" => Ok(value),
+        ":-" if value.is_empty() => word(),
+        ":-" => Ok(value),
+        ":?" if value.is_empty() => Err(ExpansionError::Unset {
+            name,
+            message: word()?,
+        }),
+        ":?" => Ok(value),
+        _ => Err(bad_substitution()),
+    }
+}
+
+/// Perform parameter expansion on a sequence of characters.
+///
+/// Expanded values are taken literally and are not subject to any further expansion.
+fn parameters(input: &[QuotedChar]) -> Result<Vec<Fragment>, ExpansionError> {
+    let mut fragments = Vec::new();
+    let mut index = 0;
+
+    while index < input.len() {
+        let (c, quoting) = input[index];
+        let rest = &input[index + 1..];
+
+        if c != '$' || quoting == Quoting::Literal {
+            append(&mut fragments, c.encode_utf8(&mut [0; 4]), quoting);
+            index += 1;
+        } else if rest.first() == Some(&('{', quoting)) {
+            let end = match closing_brace(&input[index..]) {
+                Some(end) => index + end,
+                None => {
+                    let expr: String = input[index..].iter().map(|&(c, _)| c).collect();
+                    return Err(ExpansionError::BadSubstitution(expr));
+                }
+            };
+            let value = braced(&input[index + 2..end], quoting)?;
+            append(&mut fragments, &value, Quoting::Literal);
+            index = end + 1;
+        } else {
+            let len = name_len(rest, quoting);
+            if len == 0 {
+                append(&mut fragments, "$", quoting);
+            } else {
+                let name: String = rest[..len].iter().map(|&(c, _)| c).collect();
+                append(&mut fragments, &variable(&name).unwrap_or_default(), Quoting::Literal);
+            }
+            index += 1 + len;
+        }
+    }
+
+    Ok(fragments)
+}
+
+/// Expand a single word.
+///
+/// Returns `None` if the word vanishes completely, which happens
+/// when an unquoted word expands to nothing.
+fn word(word: &Word) -> Result<Option<String>, ExpansionError> {
+    let tilded = tilde(word);
+    let word = tilded.as_ref().unwrap_or(word);
+
+    let chars: Vec<QuotedChar> = word
+        .fragments()
+        .iter()
+        .flat_map(|f| f.text.chars().map(move |c| (c, f.quoting)))
+        .collect();
+    let expanded: String = parameters(&chars)?
+        .iter()
+        .map(|f| f.text.as_str())
+        .collect();
+
+    let quoted = word.fragments().iter().any(|f| f.quoting != Quoting::Unquoted);
+    Ok(if expanded.is_empty() && !quoted {
+        None
+    } else {
+        Some(expanded)
+    })
+}
+
+/// Expand words into an argument vector.
+///
+/// Performs the following expansions, in order:
+///
+/// 1.  Tilde expansion: `~` and `~/path` are replaced by `$HOME`,
+///     `~user/path` by the home directory of that user.
+/// 2.  Parameter expansion: `$VAR` and `${VAR}` are replaced by the value
+///     of the environment variable, `${VAR:-default}` provides a default value
+///     for unset or empty variable, and `${VAR:?message}` reports such variable
+///     as an error. Parameters are expanded in unquoted and double-quoted text only.
+///
+/// Unlike in the shell, results of the expansions are never split into multiple words.
+/// Unquoted words that expand to nothing are removed.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::sh::{expand, split};
+/// let argv = expand(&split("ls ~root/bin '~/$HOME' ${NOT_SET_AT_ALL:-default}").unwrap());
+/// assert_eq!(argv.unwrap(), ["ls", "/root/bin", "~/$HOME", "default"]);
+/// ```
+pub fn expand(words: &[Word]) -> Result<Vec<String>, ExpansionError> {
+    let mut argv = Vec::with_capacity(words.len());
+    for w in words {
+        argv.extend(word(w)?);
+    }
+    Ok(argv)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sh::lex::split;
+
+    fn expand_str(input: &str) -> Vec<String> {
+        expand(&split(input).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn expands_own_home() {
+        let home = own_home().unwrap().into_string().unwrap();
+
+        assert_eq!(expand_str("~/bin/x"), [format!("{}/bin/x", home)]);
+        assert_eq!(expand_str("~"), [home]);
+    }
+
+    #[test]
+    fn expands_user_home() {
+        assert_eq!(expand_str("~root/.config"), ["/root/.config"]);
+        assert_eq!(
+            expand_str("~no-such-user-here/x"),
+            ["~no-such-user-here/x"]
+        );
+    }
+
+    #[test]
+    fn respects_quoting() {
+        assert_eq!(
+            expand_str(r#"'~' \~/a ~"root"/b "~"/c a~"#),
+            ["~", "~/a", "~root/b", "~/c", "a~"]
+        );
+    }
+
+    #[test]
+    fn expands_variables() {
+        env::set_var("URLDISPATCH_TEST_X", "a b");
+        env::set_var("URLDISPATCH_TEST_EMPTY", "");
+
+        assert_eq!(
+            expand_str("$URLDISPATCH_TEST_X ${URLDISPATCH_TEST_X}c x$URLDISPATCH_TEST_X-y"),
+            ["a b", "a bc", "xa b-y"]
+        );
+        assert_eq!(
+            expand_str("${URLDISPATCH_TEST_EMPTY:-d} ${URLDISPATCH_TEST_UNSET:-\"$URLDISPATCH_TEST_X\"}"),
+            ["d", "a b"]
+        );
+        assert_eq!(expand_str("$ $1 a$"), ["$", "$1", "a$"]);
+    }
+
+    #[test]
+    fn expansion_respects_quoting() {
+        env::set_var("URLDISPATCH_TEST_Q", "q");
+
+        assert_eq!(
+            expand_str(r#"'$URLDISPATCH_TEST_Q' "$URLDISPATCH_TEST_Q" "\$URLDISPATCH_TEST_Q""#),
+            ["$URLDISPATCH_TEST_Q", "q", "$URLDISPATCH_TEST_Q"]
+        );
+        assert_eq!(
+            expand_str(r#"a $URLDISPATCH_TEST_UNSET "$URLDISPATCH_TEST_UNSET" b"#),
+            ["a", "", "b"]
+        );
+    }
+
+    #[test]
+    fn reports_expansion_errors() {
+        let expand_err = |input| expand(&split(input).unwrap()).unwrap_err();
+
+        assert_eq!(
+            expand_err("${URLDISPATCH_TEST_UNSET:?is required}"),
+            ExpansionError::Unset {
+                name: "URLDISPATCH_TEST_UNSET".into(),
+                message: "is required".into(),
+            }
+        );
+        assert_eq!(
+            expand_err("${URLDISPATCH_TEST_UNSET"),
+            ExpansionError::BadSubstitution("${URLDISPATCH_TEST_UNSET".into())
+        );
+        assert_eq!(
+            expand_err("${X/y}"),
+            ExpansionError::BadSubstitution("${X/y}".into())
+        );
+    }
+}
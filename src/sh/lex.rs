@@ -0,0 +1,303 @@
+//! POSIX-like splitting of command lines into words.
+
+use std::{error, fmt};
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// Reasons for rejecting a command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexError {
+    /// Quote opened at the given byte offset was never closed.
+    UnterminatedQuote { quote: char, offset: usize },
+    /// Backslash at the very end of the input, with nothing to escape.
+    DanglingEscape { offset: usize },
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexError::UnterminatedQuote { quote, offset } => {
+                write!(f, "unterminated {} quote at offset {}", quote, offset)
+            }
+            LexError::DanglingEscape { offset } => {
+                write!(f, "dangling escape at offset {}", offset)
+            }
+        }
+    }
+}
+
+impl error::Error for LexError {}
+
+/// Quoting context of a part of a word.
+///
+/// Determines which expansions apply to that part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quoting {
+    /// Not quoted at all; subject to all expansions.
+    Unquoted,
+    /// Inside double quotes; subject to parameter expansion only.
+    Double,
+    /// Inside single quotes or escaped by backslash; taken literally.
+    Literal,
+}
+
+/// Continuous part of a word with uniform quoting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    pub text: String,
+    pub quoting: Quoting,
+}
+
+/// Single word of a command line, with quoting information retained.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Word {
+    fragments: Vec<Fragment>,
+}
+
+impl Word {
+    /// Parts of the word, in order.
+    pub fn fragments(&self) -> &[Fragment] {
+        &self.fragments
+    }
+
+    /// Mark the word as present, even if it ends up empty (i.e. `""`).
+    fn start(&mut self, quoting: Quoting) {
+        if self.fragments.is_empty() {
+            self.fragments.push(Fragment {
+                text: String::new(),
+                quoting,
+            });
+        }
+    }
+
+    /// Append a character with specified quoting.
+    fn push(&mut self, c: char, quoting: Quoting) {
+        match self.fragments.last_mut() {
+            Some(ref mut last) if last.quoting == quoting => last.text.push(c),
+            _ => self.fragments.push(Fragment {
+                text: c.to_string(),
+                quoting,
+            }),
+        }
+    }
+
+    /// Move a finished word (if any) to the output.
+    fn flush(&mut self, words: &mut Vec<Word>) {
+        if !self.fragments.is_empty() {
+            words.push(::std::mem::take(self));
+        }
+    }
+}
+
+impl From<Vec<Fragment>> for Word {
+    fn from(fragments: Vec<Fragment>) -> Word {
+        Word { fragments }
+    }
+}
+
+impl From<&Word> for String {
+    /// Concatenate the word parts, dropping the quoting information.
+    fn from(word: &Word) -> String {
+        word.fragments.iter().map(|f| f.text.as_str()).collect()
+    }
+}
+
+/// Characters that retain their special meaning after a backslash in double quotes.
+fn escapable_in_double_quotes(c: char) -> bool {
+    matches!(c, '$' | '`' | '"' | '\\' | '\n')
+}
+
+/// Consume a single-quoted section; the opening quote is already consumed.
+fn single_quoted(input: &mut Peekable<CharIndices>, start: usize, word: &mut Word) -> Result<(), LexError> {
+    word.start(Quoting::Literal);
+    for (_, c) in input {
+        match c {
+            '\'' => return Ok(()),
+            c => word.push(c, Quoting::Literal),
+        }
+    }
+    Err(LexError::UnterminatedQuote { quote: '\'', offset: start })
+}
+
+/// Consume a double-quoted section; the opening quote is already consumed.
+fn double_quoted(input: &mut Peekable<CharIndices>, start: usize, word: &mut Word) -> Result<(), LexError> {
+    word.start(Quoting::Double);
+    while let Some((_, c)) = input.next() {
+        match c {
+            '"' => return Ok(()),
+            '\\' => match input.peek() {
+                Some(&(_, '\n')) => {
+                    input.next();
+                }
+                Some(&(_, next)) if escapable_in_double_quotes(next) => {
+                    input.next();
+                    word.push(next, Quoting::Literal);
+                }
+                _ => word.push('\\', Quoting::Double),
+            },
+            c => word.push(c, Quoting::Double),
+        }
+    }
+    Err(LexError::UnterminatedQuote { quote: '"', offset: start })
+}
+
+/// Split a command line into words, retaining the quoting information.
+///
+/// Follows the POSIX shell quoting rules:
+///
+/// -   Unquoted whitespace separates words.
+/// -   Single quotes preserve everything up to the closing quote.
+/// -   Double quotes preserve everything except backslash escapes
+///     of `$`, `` ` ``, `"`, `\` and newline.
+/// -   Unquoted backslash preserves the following character;
+///     backslash-newline is a line continuation.
+/// -   Unquoted `${...}` is kept in one word, even if it contains whitespace.
+///
+/// The resulting words are meant to be processed by [`expand`](fn.expand.html).
+pub fn split(input: &str) -> Result<Vec<Word>, LexError> {
+    let mut words = Vec::new();
+    let mut word = Word::default();
+    let mut chars = input.char_indices().peekable();
+    // Nesting level of unquoted `${...}`, where whitespace does not separate words
+    let mut braces = 0usize;
+
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' if braces == 0 => word.flush(&mut words),
+            '$' if chars.peek().map(|&(_, c)| c) == Some('{') => {
+                chars.next();
+                braces += 1;
+                word.push('$', Quoting::Unquoted);
+                word.push('{', Quoting::Unquoted);
+            }
+            '}' if braces > 0 => {
+                braces -= 1;
+                word.push('}', Quoting::Unquoted);
+            }
+            '\'' => single_quoted(&mut chars, offset, &mut word)?,
+            '"' => double_quoted(&mut chars, offset, &mut word)?,
+            '\\' => match chars.next() {
+                Some((_, '\n')) => {}
+                Some((_, escaped)) => word.push(escaped, Quoting::Literal),
+                None => return Err(LexError::DanglingEscape { offset }),
+            },
+            c => word.push(c, Quoting::Unquoted),
+        }
+    }
+    word.flush(&mut words);
+
+    Ok(words)
+}
+
+/// Split a command line into words.
+///
+/// Same as [`split`](fn.split.html), but without any expansions
+/// and with the quoting information dropped. The resulting words are meant
+/// to be used directly as an argument vector.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::sh::lex;
+/// let argv = lex(r#"firefox --new-window "%u""#).unwrap();
+/// assert_eq!(argv, ["firefox", "--new-window", "%u"]);
+/// ```
+pub fn lex(input: &str) -> Result<Vec<String>, LexError> {
+    Ok(split(input)?.iter().map(String::from).collect())
+}
+
+/// Quote a word so that [`lex`](fn.lex.html) reads it back unchanged.
+///
+/// Words consisting of safe characters only are left as they are.
+///
+/// # Examples
+///
+/// Basic usage:
+///
+/// ```
+/// use urldispatch::sh::quote;
+/// assert_eq!(quote("/bin/true"), "/bin/true");
+/// assert_eq!(quote("it's"), r"'it'\''s'");
+/// ```
+pub fn quote(word: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "%+,-./:=@_".contains(c);
+
+    if !word.is_empty() && word.chars().all(safe) {
+        word.to_owned()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(lex("  a\tb \n c  ").unwrap(), ["a", "b", "c"]);
+        assert!(lex(" \t ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn respects_quotes() {
+        assert_eq!(lex(r#"'a "b"' "c 'd'""#).unwrap(), [r#"a "b""#, "c 'd'"]);
+        assert_eq!(lex(r#"x'y'"z"w"#).unwrap(), ["xyzw"]);
+        assert_eq!(lex(r#"'' """#).unwrap(), ["", ""]);
+    }
+
+    #[test]
+    fn handles_escapes() {
+        assert_eq!(lex(r"a\ b c\\d").unwrap(), ["a b", r"c\d"]);
+        assert_eq!(lex(r#""\$\"\x" '\n'"#).unwrap(), [r#"$"\x"#, r"\n"]);
+        assert_eq!(lex("a\\\nb").unwrap(), ["ab"]);
+    }
+
+    #[test]
+    fn keeps_braces_together() {
+        assert_eq!(lex("a ${X:-b c}d e").unwrap(), ["a", "${X:-b c}d", "e"]);
+        assert_eq!(lex("${X:-${Y:-a b} c} d}").unwrap(), ["${X:-${Y:-a b} c}", "d}"]);
+    }
+
+    #[test]
+    fn retains_quoting() {
+        let words = split(r#"~/a'$b'"\$c$d""#).unwrap();
+        let quoting: Vec<_> = words[0]
+            .fragments()
+            .iter()
+            .map(|f| (f.text.as_str(), f.quoting))
+            .collect();
+
+        assert_eq!(
+            quoting,
+            [
+                ("~/a", Quoting::Unquoted),
+                ("$b$", Quoting::Literal),
+                ("c$d", Quoting::Double),
+            ]
+        );
+    }
+
+    #[test]
+    fn quote_roundtrips() {
+        let words = ["a", "", "a b", "'", r#"\"$x"#, "~/x", "${X}", "%u"];
+        for word in &words {
+            assert_eq!(lex(&quote(word)).unwrap(), [*word]);
+        }
+    }
+
+    #[test]
+    fn reports_unterminated_quotes() {
+        assert_eq!(
+            lex("echo 'abc"),
+            Err(LexError::UnterminatedQuote { quote: '\'', offset: 5 })
+        );
+        assert_eq!(
+            lex(r#"a "b\""#),
+            Err(LexError::UnterminatedQuote { quote: '"', offset: 2 })
+        );
+        assert_eq!(lex("a\\"), Err(LexError::DanglingEscape { offset: 1 }));
+    }
+}
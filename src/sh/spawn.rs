@@ -0,0 +1,262 @@
+//! Dispatch through `posix_spawn`, without forking the caller.
+//!
+//! Unlike `fork`, `posix_spawn` does not run any code of the caller
+//! in the new process, so it is safe to use in multithreaded programs.
+//! The price is that only part of the `process::Command` is honoured:
+//! the program, arguments, environment changes and working directory.
+//! Standard stream configuration and `env_clear` are not observable
+//! and thus ignored; the streams are set up according to the `Detach` options.
+//!
+//! The command is a child of the caller, which is responsible for reaping it.
+//!
+//! Changing the working directory and closing the inherited descriptors
+//! rely on glibc extensions; elsewhere, commands needing them are dispatched
+//! with the fork backend instead (see [`supports`](fn.supports.html)).
+
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::{env, iter, mem, process, ptr};
+
+use nix::errno::Errno;
+use nix::libc::{self, c_char, c_int, c_short};
+use nix::unistd::Pid;
+
+use super::{Detach, Output};
+use Error;
+
+/// Turn the return code of `posix_spawn*` functions into result.
+fn check(code: c_int) -> Result<(), Error> {
+    match code {
+        0 => Ok(()),
+        code => Err(Error::Os(Errno::from_i32(code))),
+    }
+}
+
+/// Actions performed in the new process before executing the command.
+struct FileActions(libc::posix_spawn_file_actions_t);
+
+impl FileActions {
+    fn new() -> Result<FileActions, Error> {
+        let mut actions = unsafe { mem::zeroed() };
+        check(unsafe { libc::posix_spawn_file_actions_init(&mut actions) })?;
+        Ok(FileActions(actions))
+    }
+
+    fn dup2(&mut self, fd: RawFd, target: RawFd) -> Result<(), Error> {
+        check(unsafe { libc::posix_spawn_file_actions_adddup2(&mut self.0, fd, target) })
+    }
+
+    #[cfg(target_env = "gnu")]
+    fn chdir(&mut self, path: &CStr) -> Result<(), Error> {
+        check(unsafe { libc::posix_spawn_file_actions_addchdir_np(&mut self.0, path.as_ptr()) })
+    }
+
+    #[cfg(target_env = "gnu")]
+    fn close_from(&mut self, fd: RawFd) -> Result<(), Error> {
+        check(unsafe { libc::posix_spawn_file_actions_addclosefrom_np(&mut self.0, fd) })
+    }
+}
+
+impl Drop for FileActions {
+    fn drop(&mut self) {
+        unsafe { libc::posix_spawn_file_actions_destroy(&mut self.0) };
+    }
+}
+
+/// Attributes of the new process.
+struct Attributes(libc::posix_spawnattr_t);
+
+impl Attributes {
+    /// Attributes resetting the signal mask and `SIGPIPE` disposition,
+    /// as `process::Command` does.
+    fn new(new_session: bool) -> Result<Attributes, Error> {
+        let mut attributes = unsafe { mem::zeroed() };
+        check(unsafe { libc::posix_spawnattr_init(&mut attributes) })?;
+        let mut attributes = Attributes(attributes);
+
+        let mut flags = libc::POSIX_SPAWN_SETSIGMASK | libc::POSIX_SPAWN_SETSIGDEF;
+        if new_session {
+            flags |= c_int::from(libc::POSIX_SPAWN_SETSID);
+        }
+        unsafe {
+            let mut signals = mem::zeroed();
+            libc::sigemptyset(&mut signals);
+            check(libc::posix_spawnattr_setsigmask(&mut attributes.0, &signals))?;
+            libc::sigaddset(&mut signals, libc::SIGPIPE);
+            check(libc::posix_spawnattr_setsigdefault(&mut attributes.0, &signals))?;
+            check(libc::posix_spawnattr_setflags(&mut attributes.0, flags as c_short))?;
+        }
+        Ok(attributes)
+    }
+}
+
+impl Drop for Attributes {
+    fn drop(&mut self) {
+        unsafe { libc::posix_spawnattr_destroy(&mut self.0) };
+    }
+}
+
+/// Check if the backend can honour the options and the command
+/// on this platform, otherwise the fork backend needs to be used.
+pub fn supports(command: &process::Command, options: &Detach) -> bool {
+    cfg!(target_env = "gnu") || !(options.close_fds || options.chdir_root || command.get_current_dir().is_some())
+}
+
+/// Variables of the command: the current ones with the command's changes.
+fn variables(command: &process::Command) -> BTreeMap<OsString, OsString> {
+    let mut variables: BTreeMap<OsString, OsString> = env::vars_os().collect();
+    for (name, value) in command.get_envs() {
+        match value {
+            Some(value) => variables.insert(name.to_owned(), value.to_owned()),
+            None => variables.remove(name),
+        };
+    }
+    variables
+}
+
+/// Environment of the command, as `NAME=value` strings.
+fn environment(variables: &BTreeMap<OsString, OsString>) -> Option<Vec<CString>> {
+    variables
+        .iter()
+        .map(|(name, value)| {
+            let mut entry = name.as_bytes().to_vec();
+            entry.push(b'=');
+            entry.extend_from_slice(value.as_bytes());
+            CString::new(entry).ok()
+        })
+        .collect()
+}
+
+/// Find the program in the directories of `PATH`, as `execvp` does.
+///
+/// Programs containing a slash are used as they are.
+/// Relative directories, including the empty one meaning the working directory,
+/// are skipped unless `relative` is set: they would be searched from the caller's
+/// working directory, not from the one the command is started in.
+fn resolve(program: &OsStr, path: &OsStr, relative: bool) -> Result<PathBuf, Errno> {
+    if program.as_bytes().contains(&b'/') {
+        return Ok(program.into());
+    }
+
+    let mut found = false;
+    for directory in env::split_paths(path).filter(|directory| relative || directory.is_absolute()) {
+        let candidate = directory.join(program);
+        if let Ok(metadata) = fs::metadata(&candidate) {
+            if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
+                return Ok(candidate);
+            }
+            found = true;
+        }
+    }
+    Err(if found { Errno::EACCES } else { Errno::ENOENT })
+}
+
+/// Null-terminated array of pointers to the strings.
+fn pointers(strings: &[CString]) -> Vec<*mut c_char> {
+    strings
+        .iter()
+        .map(|string| string.as_ptr() as *mut c_char)
+        .chain(iter::once(ptr::null_mut()))
+        .collect()
+}
+
+/// Starts the command with `posix_spawn`; returns its PID.
+///
+/// Unlike with `fork`, the command is a child of the caller, which should reap it.
+pub fn spawn(command: &process::Command, options: &Detach) -> Result<Pid, Error> {
+    let program = command.get_program();
+    let invalid = || Error::exec(program, Errno::EINVAL);
+    let argv = iter::once(program)
+        .chain(command.get_args())
+        .map(|arg| CString::new(arg.as_bytes()).map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let variables = variables(command);
+    let envp = environment(&variables).ok_or_else(invalid)?;
+    // posix_spawnp would search the caller's PATH, not the command's one
+    let executable = match variables.get(OsStr::new("PATH")) {
+        Some(path) => {
+            let relative = !options.chdir_root && command.get_current_dir().is_none();
+            let resolved = resolve(program, path, relative).map_err(|errno| Error::exec(program, errno))?;
+            Some(CString::new(resolved.into_os_string().into_vec()).map_err(|_| invalid())?)
+        }
+        None => None,
+    };
+
+    let open = |path: &Path, log: bool| {
+        OpenOptions::new()
+            .read(!log)
+            .write(true)
+            .append(log)
+            .create(log)
+            .open(path)
+            .map_err(|err| Error::Detach(path.into(), Errno::from_i32(err.raw_os_error().unwrap_or(0))))
+    };
+    let mut actions = FileActions::new()?;
+    // The files must stay open until the command is started
+    let mut files: Vec<File> = Vec::new();
+    match options.output {
+        Output::Inherit => {}
+        Output::Null => {
+            let null = open(Path::new("/dev/null"), false)?;
+            for target in 0..3 {
+                actions.dup2(null.as_raw_fd(), target)?;
+            }
+            files.push(null);
+        }
+        Output::Log(ref path) => {
+            let null = open(Path::new("/dev/null"), false)?;
+            let log = open(path, true)?;
+            actions.dup2(null.as_raw_fd(), 0)?;
+            actions.dup2(log.as_raw_fd(), 1)?;
+            actions.dup2(log.as_raw_fd(), 2)?;
+            files.extend(vec![null, log]);
+        }
+    }
+    debug_assert!(supports(command, options));
+    #[cfg(target_env = "gnu")]
+    {
+        if options.close_fds {
+            actions.close_from(3)?;
+        }
+        if options.chdir_root {
+            actions.chdir(&CString::new("/").unwrap())?;
+        }
+        if let Some(directory) = command.get_current_dir() {
+            actions.chdir(&CString::new(directory.as_os_str().as_bytes()).map_err(|_| invalid())?)?;
+        }
+    }
+    let attributes = Attributes::new(options.new_session)?;
+
+    let (argv_pointers, envp_pointers) = (pointers(&argv), pointers(&envp));
+    let mut pid = 0;
+    let code = unsafe {
+        match executable {
+            Some(ref executable) => libc::posix_spawn(
+                &mut pid,
+                executable.as_ptr(),
+                &actions.0,
+                &attributes.0,
+                argv_pointers.as_ptr(),
+                envp_pointers.as_ptr(),
+            ),
+            None => libc::posix_spawnp(
+                &mut pid,
+                argv[0].as_ptr(),
+                &actions.0,
+                &attributes.0,
+                argv_pointers.as_ptr(),
+                envp_pointers.as_ptr(),
+            ),
+        }
+    };
+    if code != 0 {
+        return Err(Error::exec(program, Errno::from_i32(code)));
+    }
+
+    Ok(Pid::from_raw(pid))
+}
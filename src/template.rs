@@ -0,0 +1,347 @@
+//! Launcher command templates with URL placeholders.
+//!
+//! Template is a command line (see [`sh::split`](../sh/fn.split.html))
+//! with the following placeholders:
+//!
+//! -   `%u`: full URL,
+//! -   `%s`: scheme,
+//! -   `%h`: host (empty if there is none),
+//! -   `%p`: path,
+//! -   `%q`: query (empty if there is none),
+//! -   `%f`: local file path (only for `file://` URLs),
+//! -   `%U`: all URLs, each as a separate word,
+//! -   `%F`: local file paths of all URLs, each as a separate word,
+//! -   `%%`: literal `%`.
+//!
+//! Placeholders are substituted in each word separately, and the substituted
+//! values are never split, expanded or otherwise interpreted.
+//! `%U` and `%F` must form a word on their own; templates with them
+//! open multiple URLs at once, others receive a single URL.
+
+use std::str::FromStr;
+use std::{error, fmt, process, slice};
+
+use url::Url;
+
+use sh::{self, ExpansionError, Fragment, LexError, Quoting, Word};
+
+/// Reasons for an unusable template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// The command line could not be split into words.
+    Lex(LexError),
+    /// The words could not be expanded.
+    Expansion(ExpansionError),
+    /// Unknown or incomplete placeholder.
+    UnknownPlaceholder(String),
+    /// Placeholder for multiple URLs that is not a separate word.
+    EmbeddedList(String),
+    /// `%f` used with an URL that does not refer to a local file.
+    NotLocalFile(String),
+    /// There is no program to execute.
+    Empty,
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TemplateError::Lex(ref err) => err.fmt(f),
+            TemplateError::Expansion(ref err) => err.fmt(f),
+            TemplateError::UnknownPlaceholder(ref p) => write!(f, "unknown placeholder: {}", p),
+            TemplateError::EmbeddedList(ref p) => write!(f, "{} must be a separate word", p),
+            TemplateError::NotLocalFile(ref url) => write!(f, "not a local file: {}", url),
+            TemplateError::Empty => write!(f, "empty command"),
+        }
+    }
+}
+
+impl error::Error for TemplateError {}
+
+impl From<LexError> for TemplateError {
+    fn from(err: LexError) -> TemplateError {
+        TemplateError::Lex(err)
+    }
+}
+impl From<ExpansionError> for TemplateError {
+    fn from(err: ExpansionError) -> TemplateError {
+        TemplateError::Expansion(err)
+    }
+}
+
+/// Piece of a fragment text.
+enum Piece<'t> {
+    Text(&'t str),
+    Placeholder(char),
+}
+
+/// Break text into literal pieces and placeholders.
+fn pieces<'t>(text: &'t str) -> Result<Vec<Piece<'t>>, TemplateError> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+
+    while let Some(percent) = rest.find('%') {
+        if percent > 0 {
+            pieces.push(Piece::Text(&rest[..percent]));
+        }
+        let mut chars = rest[percent + 1..].chars();
+        match chars.next() {
+            Some('%') => pieces.push(Piece::Text("%")),
+            Some(c) if "usphqfUF".contains(c) => pieces.push(Piece::Placeholder(c)),
+            Some(c) => return Err(TemplateError::UnknownPlaceholder(format!("%{}", c))),
+            None => return Err(TemplateError::UnknownPlaceholder("%".into())),
+        }
+        rest = chars.as_str();
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest));
+    }
+
+    Ok(pieces)
+}
+
+/// Placeholder for multiple URLs (`U` or `F`) forming the whole word, if any.
+fn list(word: &Word) -> Result<Option<char>, TemplateError> {
+    let mut pieces = Vec::new();
+    for fragment in word.fragments() {
+        pieces.extend(self::pieces(&fragment.text)?);
+    }
+    let embedded = pieces.iter().find_map(|piece| match *piece {
+        Piece::Placeholder(name) if name.is_ascii_uppercase() => Some(name),
+        _ => None,
+    });
+
+    match (embedded, pieces.len()) {
+        (Some(name), 1) => Ok(Some(name)),
+        (Some(name), _) => Err(TemplateError::EmbeddedList(format!("%{}", name))),
+        (None, _) => Ok(None),
+    }
+}
+
+/// Value of a placeholder for the URL; empty without any URL.
+fn placeholder(name: char, url: Option<&Url>) -> Result<String, TemplateError> {
+    let url = match url {
+        Some(url) => url,
+        None => return Ok(String::new()),
+    };
+    Ok(match name.to_ascii_lowercase() {
+        'u' => url.as_str().to_owned(),
+        's' => url.scheme().to_owned(),
+        'h' => url.host_str().unwrap_or_default().to_owned(),
+        'p' => url.path().to_owned(),
+        'q' => url.query().unwrap_or_default().to_owned(),
+        'f' => match url.to_file_path() {
+            Ok(ref path) if url.scheme() == "file" => path.to_string_lossy().into_owned(),
+            _ => return Err(TemplateError::NotLocalFile(url.as_str().to_owned())),
+        },
+        other => unreachable!("placeholder %{} passed validation", other),
+    })
+}
+
+/// Command line template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    words: Vec<Word>,
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    /// Parse and validate a template.
+    fn from_str(source: &str) -> Result<Template, TemplateError> {
+        let words = sh::split(source)?;
+        if words.is_empty() {
+            return Err(TemplateError::Empty);
+        }
+        for word in &words {
+            list(word)?;
+        }
+
+        Ok(Template {
+            source: source.to_owned(),
+            words,
+        })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Template {
+    /// Substitute placeholders in a word.
+    fn substitute(word: &Word, url: Option<&Url>) -> Result<Word, TemplateError> {
+        let mut fragments = Vec::with_capacity(word.fragments().len());
+
+        for fragment in word.fragments() {
+            for piece in pieces(&fragment.text)? {
+                fragments.push(match piece {
+                    Piece::Text(text) => Fragment {
+                        text: text.to_owned(),
+                        quoting: fragment.quoting,
+                    },
+                    Piece::Placeholder(name) => Fragment {
+                        text: placeholder(name, url)?,
+                        quoting: Quoting::Literal,
+                    },
+                });
+            }
+        }
+
+        Ok(fragments.into())
+    }
+
+    /// Produce an argument vector for the URL.
+    ///
+    /// The placeholders are substituted first, then the words are expanded
+    /// (see [`sh::expand`](../sh/fn.expand.html)).
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate urldispatch;
+    /// use urldispatch::template::Template;
+    /// let template: Template = "firefox --new-window %u".parse().unwrap();
+    /// let url = url::Url::parse("https://example.com/a b?$HOME").unwrap();
+    /// assert_eq!(
+    ///     template.argv(&url).unwrap(),
+    ///     ["firefox", "--new-window", "https://example.com/a%20b?$HOME"]
+    /// );
+    /// ```
+    pub fn argv(&self, url: &Url) -> Result<Vec<String>, TemplateError> {
+        self.argv_all(slice::from_ref(url))
+    }
+
+    /// Check if the template opens multiple URLs at once, with `%U` or `%F`.
+    pub fn accepts_multiple(&self) -> bool {
+        self.words.iter().any(|word| list(word).ok().flatten().is_some())
+    }
+
+    /// Produce an argument vector for multiple URLs.
+    ///
+    /// `%U` and `%F` are replaced by a word for each URL;
+    /// the other placeholders receive only the first URL.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// # extern crate url;
+    /// # extern crate urldispatch;
+    /// use urldispatch::template::Template;
+    /// let template: Template = "mpv --title=%h -- %U".parse().unwrap();
+    /// let urls = [
+    ///     url::Url::parse("https://example.com/a").unwrap(),
+    ///     url::Url::parse("https://example.org/b").unwrap(),
+    /// ];
+    /// assert!(template.accepts_multiple());
+    /// assert_eq!(
+    ///     template.argv_all(&urls).unwrap(),
+    ///     ["mpv", "--title=example.com", "--", "https://example.com/a", "https://example.org/b"]
+    /// );
+    /// ```
+    pub fn argv_all(&self, urls: &[Url]) -> Result<Vec<String>, TemplateError> {
+        let mut words = Vec::with_capacity(self.words.len() + urls.len());
+        for word in &self.words {
+            match list(word)? {
+                Some(name) => {
+                    for url in urls {
+                        let fragment = Fragment {
+                            text: placeholder(name, Some(url))?,
+                            quoting: Quoting::Literal,
+                        };
+                        words.push(vec![fragment].into());
+                    }
+                }
+                None => words.push(Template::substitute(word, urls.first())?),
+            }
+        }
+
+        Ok(sh::expand(&words)?)
+    }
+
+    /// Prepare a command for [`sh::dispatch`](../sh/fn.dispatch.html).
+    pub fn command(&self, url: &Url) -> Result<process::Command, TemplateError> {
+        sh::command(self.argv(url)?).ok_or(TemplateError::Empty)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn argv(template: &str, url: &str) -> Result<Vec<String>, TemplateError> {
+        let template: Template = template.parse()?;
+        template.argv(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn substitutes_placeholders() {
+        assert_eq!(
+            argv("x %s %h %p %q %%u", "https://example.com/p/a?x=1").unwrap(),
+            ["x", "https", "example.com", "/p/a", "x=1", "%u"]
+        );
+        assert_eq!(
+            argv("open --file=%f", "file:///tmp/a%20b").unwrap(),
+            ["open", "--file=/tmp/a b"]
+        );
+    }
+
+    #[test]
+    fn substitutes_lists() {
+        let template: Template = "open %f '%F'".parse().unwrap();
+        let urls: Vec<_> = ["file:///tmp/a", "file:///tmp/b%20c"]
+            .iter()
+            .map(|url| Url::parse(url).unwrap())
+            .collect();
+        assert!(template.accepts_multiple());
+        assert_eq!(template.argv_all(&urls).unwrap(), ["open", "/tmp/a", "/tmp/a", "/tmp/b c"]);
+        assert_eq!(template.argv_all(&[]).unwrap(), ["open", ""]);
+        assert!(!"open %f".parse::<Template>().unwrap().accepts_multiple());
+
+        let web = Url::parse("https://example.com/").unwrap();
+        assert_eq!(
+            template.argv_all(&[urls[0].clone(), web]),
+            Err(TemplateError::NotLocalFile("https://example.com/".into()))
+        );
+    }
+
+    #[test]
+    fn does_not_interpret_url() {
+        assert_eq!(
+            argv("echo %u '%u'", "https://example.com/$(rm)?a=$HOME&b=~;c").unwrap(),
+            [
+                "echo",
+                "https://example.com/$(rm)?a=$HOME&b=~;c",
+                "https://example.com/$(rm)?a=$HOME&b=~;c",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_templates() {
+        assert_eq!(
+            "echo %x".parse::<Template>(),
+            Err(TemplateError::UnknownPlaceholder("%x".into()))
+        );
+        assert_eq!(
+            "echo 100%".parse::<Template>(),
+            Err(TemplateError::UnknownPlaceholder("%".into()))
+        );
+        assert_eq!(" ".parse::<Template>(), Err(TemplateError::Empty));
+        assert_eq!(
+            "mpv --urls=%U".parse::<Template>(),
+            Err(TemplateError::EmbeddedList("%U".into()))
+        );
+        assert_eq!(
+            argv("open %f", "https://example.com/"),
+            Err(TemplateError::NotLocalFile("https://example.com/".into()))
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Locations from the XDG Base Directory specification.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Absolute path from an environment variable, if set.
+fn absolute_var(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+}
+
+/// Absolute paths from a colon-separated environment variable, or the defaults.
+fn absolute_list(name: &str, defaults: &[&str]) -> Vec<PathBuf> {
+    let list: Vec<_> = env::var_os(name)
+        .map(|value| env::split_paths(&value).filter(|path| path.is_absolute()).collect())
+        .unwrap_or_default();
+
+    if list.is_empty() {
+        defaults.iter().map(PathBuf::from).collect()
+    } else {
+        list
+    }
+}
+
+/// Directory relative to the user home directory.
+fn in_home(relative: &str) -> Option<PathBuf> {
+    env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(|home| Path::new(&home).join(relative))
+}
+
+/// User configuration directory (`$XDG_CONFIG_HOME`, `~/.config`).
+pub fn config_home() -> Option<PathBuf> {
+    absolute_var("XDG_CONFIG_HOME").or_else(|| in_home(".config"))
+}
+
+/// System configuration directories (`$XDG_CONFIG_DIRS`, `/etc/xdg`).
+pub fn config_dirs() -> Vec<PathBuf> {
+    absolute_list("XDG_CONFIG_DIRS", &["/etc/xdg"])
+}
+
+/// All configuration directories, in order of preference.
+pub fn config_search_path() -> Vec<PathBuf> {
+    config_home().into_iter().chain(config_dirs()).collect()
+}
+
+/// User data directory (`$XDG_DATA_HOME`, `~/.local/share`).
+pub fn data_home() -> Option<PathBuf> {
+    absolute_var("XDG_DATA_HOME").or_else(|| in_home(".local/share"))
+}
+
+/// System data directories (`$XDG_DATA_DIRS`, `/usr/local/share:/usr/share`).
+pub fn data_dirs() -> Vec<PathBuf> {
+    absolute_list("XDG_DATA_DIRS", &["/usr/local/share", "/usr/share"])
+}
+
+/// All data directories, in order of preference.
+pub fn data_search_path() -> Vec<PathBuf> {
+    data_home().into_iter().chain(data_dirs()).collect()
+}
+
+/// Find the most important existing file in the search path.
+pub fn find<P: AsRef<Path>>(search_path: &[PathBuf], relative: P) -> Option<PathBuf> {
+    search_path
+        .iter()
+        .map(|dir| dir.join(relative.as_ref()))
+        .find(|path| path.is_file())
+}
+